@@ -0,0 +1,139 @@
+//! Audit stream of the requests the proxy refused by itself.
+//!
+//! Every request rejected before reaching an upstream (ambiguous framing, limits, shutdown...)
+//! is reported as an [`AuditEvent`] to the [`AUDIT_SINK`], separately from upstream failures.
+//! By default events are logged as `warn` records with the `warp_reverse_proxy::audit` target.
+use crate::errors;
+use once_cell::sync::OnceCell;
+use warp::filters::path::FullPath;
+use warp::http::{Method, StatusCode};
+use warp::Filter;
+
+/// Audit sink receiving the denied requests.
+///
+/// It can be overridden calling `OnceCell::set` as follows:
+/// # Examples
+/// ```
+/// use warp_reverse_proxy::audit::{AuditEvent, AuditSink, AUDIT_SINK};
+///
+/// let sink: Box<dyn AuditSink> = Box::new(|event: &AuditEvent| println!("{}", event));
+/// AUDIT_SINK.set(sink).ok().expect("audit sink is set");
+/// ```
+pub static AUDIT_SINK: OnceCell<Box<dyn AuditSink>> = OnceCell::new();
+
+/// A request refused by the proxy.
+#[derive(Clone, Debug)]
+pub struct AuditEvent {
+    pub method: Method,
+    pub path: String,
+    /// Identifier of the check that refused the request, such as `header_limits`
+    pub rule: &'static str,
+    /// Human readable reason
+    pub reason: String,
+    /// Status replied by [`errors::recover`](../errors/fn.recover.html)
+    pub status: StatusCode,
+}
+
+/// Destination of the [`AuditEvent`]s.
+pub trait AuditSink: Send + Sync {
+    fn record(&self, event: &AuditEvent);
+}
+
+impl<F> AuditSink for F
+where
+    F: Fn(&AuditEvent) + Send + Sync,
+{
+    fn record(&self, event: &AuditEvent) {
+        self(event)
+    }
+}
+
+impl std::fmt::Display for AuditEvent {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "method={} path={:?} rule={} status={} reason={:?}",
+            self.method,
+            self.path,
+            self.rule,
+            self.status.as_u16(),
+            self.reason
+        )
+    }
+}
+
+/// Reports a refused request and hands the error back to be returned.
+pub(crate) fn denied(
+    method: &Method,
+    path: &str,
+    rule: &'static str,
+    error: errors::Error,
+) -> errors::Error {
+    record(method, path, rule, &error.to_string(), error.status_code());
+    error
+}
+
+/// Reports a request refused with a reply built by the proxy rather than an error.
+pub(crate) fn record(
+    method: &Method,
+    path: &str,
+    rule: &'static str,
+    reason: &str,
+    status: StatusCode,
+) {
+    let event = AuditEvent {
+        method: method.clone(),
+        path: path.to_string(),
+        rule,
+        reason: reason.to_string(),
+        status,
+    };
+    match AUDIT_SINK.get() {
+        Some(sink) => sink.record(&event),
+        None => log::warn!(target: "warp_reverse_proxy::audit", "{}", event),
+    }
+}
+
+/// Extracts what an audit event needs to know about the request.
+pub(crate) fn request_line(
+) -> impl Filter<Extract = (Method, FullPath), Error = std::convert::Infallible> + Clone {
+    warp::method().and(warp::path::full())
+}
+
+#[cfg(test)]
+mod test {
+    use super::{AuditEvent, AUDIT_SINK};
+    use crate::limits::HeaderLimits;
+    use std::sync::Mutex;
+    use warp::http::StatusCode;
+    use warp::Filter;
+
+    static EVENTS: Mutex<Vec<AuditEvent>> = Mutex::new(Vec::new());
+
+    #[tokio::test]
+    async fn denied_requests_are_audited() {
+        AUDIT_SINK
+            .set(Box::new(|event: &AuditEvent| {
+                EVENTS.lock().unwrap().push(event.clone())
+            }))
+            .ok()
+            .unwrap();
+        let filter = HeaderLimits::new().max_count(0).filter().map(warp::reply);
+
+        warp::test::request()
+            .method("POST")
+            .path("/audited/path?foo")
+            .header("foo", "bar")
+            .reply(&filter)
+            .await;
+
+        let events = EVENTS.lock().unwrap();
+        let event = events
+            .iter()
+            .find(|event| event.path == "/audited/path")
+            .unwrap();
+        assert_eq!(event.method, "POST");
+        assert_eq!(event.rule, "header_limits");
+        assert_eq!(event.status, StatusCode::REQUEST_HEADER_FIELDS_TOO_LARGE);
+    }
+}
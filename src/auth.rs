@@ -0,0 +1,345 @@
+//! Authentication a route requires before forwarding requests.
+//!
+//! Unauthenticated requests are answered by the route itself, `401 Unauthorized` with a
+//! `WWW-Authenticate` challenge or the forward-auth service reply, and reported to the
+//! [`audit`](../audit/index.html) stream. They are not rejected, so they never fall through to
+//! the next routes of a [`ProxyConfig`](../config/struct.ProxyConfig.html). Neither do requests
+//! the forward-auth service can't be asked about: the route replies them `502 Bad Gateway`, or
+//! `504 Gateway Timeout`.
+use crate::headers::{checked_value, HopHeaderPolicy};
+use crate::{audit, buffered_reply, constant_time_eq, default_client, errors, Request};
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use std::sync::Arc;
+use warp::http::{self, header, HeaderName, HeaderValue, StatusCode};
+use warp::hyper::Body;
+
+/// Header telling a forward-auth service the method of the original request
+pub static FORWARDED_METHOD: HeaderName = HeaderName::from_static("x-forwarded-method");
+/// Header telling a forward-auth service the path and query of the original request
+pub static FORWARDED_URI: HeaderName = HeaderName::from_static("x-forwarded-uri");
+
+/// Authentication requirement of a route.
+/// # Examples
+/// ```
+/// use warp::http::HeaderName;
+/// use warp_reverse_proxy::auth::Auth;
+/// use warp_reverse_proxy::config::{ProxyConfig, Route};
+///
+/// let config = ProxyConfig::new(([0, 0, 0, 0], 3030).into())
+///     .route(Route::new("admin", "http://admin.local").auth(Auth::basic("admin").user("ops", "secret")))
+///     .route(Route::new("app", "http://app.local").auth(
+///         Auth::forward("http://auth.local/verify").copy_header(HeaderName::from_static("x-user")),
+///     ))
+///     .route(Route::new("", "http://www.local").auth(Auth::none()));
+/// ```
+#[derive(Clone, Debug)]
+pub struct Auth(Kind);
+
+#[derive(Clone)]
+enum Kind {
+    None,
+    Basic {
+        realm: String,
+        users: Vec<(String, String)>,
+    },
+    Bearer {
+        realm: String,
+        validate: Arc<dyn Fn(&str) -> bool + Send + Sync>,
+    },
+    Forward {
+        url: String,
+        copy_headers: Vec<HeaderName>,
+    },
+}
+
+impl std::fmt::Debug for Kind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Kind::None => f.write_str("None"),
+            Kind::Basic { realm, users } => f
+                .debug_struct("Basic")
+                .field("realm", realm)
+                .field("users", &users.len())
+                .finish(),
+            Kind::Bearer { realm, .. } => f.debug_struct("Bearer").field("realm", realm).finish(),
+            Kind::Forward { url, copy_headers } => f
+                .debug_struct("Forward")
+                .field("url", url)
+                .field("copy_headers", copy_headers)
+                .finish(),
+        }
+    }
+}
+
+impl Auth {
+    /// No authentication, for public routes.
+    pub fn none() -> Self {
+        Self(Kind::None)
+    }
+
+    /// HTTP Basic authentication against the [`user`](#method.user)s.
+    pub fn basic(realm: impl Into<String>) -> Self {
+        Self(Kind::Basic {
+            realm: realm.into(),
+            users: Vec::new(),
+        })
+    }
+
+    /// Adds a user to Basic authentication.
+    pub fn user(mut self, name: impl Into<String>, password: impl Into<String>) -> Self {
+        if let Kind::Basic { users, .. } = &mut self.0 {
+            users.push((name.into(), password.into()));
+        }
+        self
+    }
+
+    /// Bearer tokens accepted by `validate`, such as JWTs checked with the application JWT
+    /// library.
+    pub fn bearer<F>(realm: impl Into<String>, validate: F) -> Self
+    where
+        F: Fn(&str) -> bool + Send + Sync + 'static,
+    {
+        Self(Kind::Bearer {
+            realm: realm.into(),
+            validate: Arc::new(validate),
+        })
+    }
+
+    /// Asks the service at `url` to authenticate requests.
+    ///
+    /// It gets a `GET` with the request `Authorization` and `Cookie` headers, and the original
+    /// method and URI in `X-Forwarded-Method` and `X-Forwarded-Uri`. A `2xx` reply lets the
+    /// request through, any other one is replied to the client as is, such as a redirection
+    /// to a login page.
+    pub fn forward(url: impl Into<String>) -> Self {
+        Self(Kind::Forward {
+            url: url.into(),
+            copy_headers: Vec::new(),
+        })
+    }
+
+    /// Copies `header` from the forward-auth service reply to the upstream request, replacing
+    /// any value sent by the client.
+    pub fn copy_header(mut self, header: HeaderName) -> Self {
+        if let Kind::Forward { copy_headers, .. } = &mut self.0 {
+            copy_headers.push(header);
+        }
+        self
+    }
+
+    /// Authenticates a request, returning the reply to send instead of forwarding it when it
+    /// is refused.
+    pub(crate) async fn authenticate(
+        &self,
+        request: &mut Request,
+    ) -> Result<Option<http::Response<Body>>, errors::Error> {
+        let challenge = match &self.0 {
+            Kind::None => return Ok(None),
+            Kind::Basic { realm, users } => {
+                let credentials = credentials(&request.3, "Basic")
+                    .and_then(|encoded| STANDARD.decode(encoded).ok())
+                    .and_then(|decoded| String::from_utf8(decoded).ok());
+                let valid = credentials
+                    .as_ref()
+                    .and_then(|credentials| credentials.split_once(':'))
+                    .is_some_and(|(name, password)| {
+                        users.iter().fold(false, |found, (n, p)| {
+                            let matches = constant_time_eq(n.as_bytes(), name.as_bytes())
+                                & constant_time_eq(p.as_bytes(), password.as_bytes());
+                            found | matches
+                        })
+                    });
+                match valid {
+                    true => return Ok(None),
+                    false => format!("Basic realm=\"{}\"", realm),
+                }
+            }
+            Kind::Bearer { realm, validate } => {
+                match credentials(&request.3, "Bearer").is_some_and(|token| validate(token)) {
+                    true => return Ok(None),
+                    false => format!("Bearer realm=\"{}\"", realm),
+                }
+            }
+            Kind::Forward { url, copy_headers } => {
+                return forward(url, copy_headers, request).await;
+            }
+        };
+        let reason = "missing or invalid credentials";
+        refused(request, StatusCode::UNAUTHORIZED, reason);
+        let mut reply = http::Response::new(Body::from(reason));
+        *reply.status_mut() = StatusCode::UNAUTHORIZED;
+        reply
+            .headers_mut()
+            .insert(header::WWW_AUTHENTICATE, checked_value(&challenge)?);
+        Ok(Some(reply))
+    }
+}
+
+/// Credentials of an `Authorization` header using `scheme`.
+fn credentials<'a>(headers: &'a http::HeaderMap, scheme: &str) -> Option<&'a str> {
+    let value = headers.get(header::AUTHORIZATION)?.to_str().ok()?;
+    let (request_scheme, credentials) = value.split_once(' ')?;
+    request_scheme
+        .eq_ignore_ascii_case(scheme)
+        .then(|| credentials.trim())
+}
+
+async fn forward(
+    url: &str,
+    copy_headers: &[HeaderName],
+    request: &mut Request,
+) -> Result<Option<http::Response<Body>>, errors::Error> {
+    let uri = match &request.1 {
+        Some(query) => format!("{}?{}", request.0.as_str(), query),
+        None => request.0.as_str().to_string(),
+    };
+    let mut check = default_client()
+        .get(url)
+        .header(&FORWARDED_METHOD, request.2.as_str())
+        .header(&FORWARDED_URI, checked_value(&uri)?);
+    for name in [header::AUTHORIZATION, header::COOKIE] {
+        for value in request.3.get_all(&name) {
+            check = check.header(&name, value);
+        }
+    }
+    let response = check.send().await?;
+    if !response.status().is_success() {
+        refused(
+            request,
+            response.status(),
+            "refused by the forward-auth service",
+        );
+        return buffered_reply(&HopHeaderPolicy::default(), response)
+            .await
+            .map(Some);
+    }
+    for name in copy_headers {
+        request.3.remove(name);
+        for value in response.headers().get_all(name) {
+            request.3.append(name, HeaderValue::clone(value));
+        }
+    }
+    Ok(None)
+}
+
+fn refused(request: &Request, status: StatusCode, reason: &str) {
+    audit::record(&request.2, request.0.as_str(), "auth", reason, status);
+}
+
+#[cfg(test)]
+mod test {
+    use super::Auth;
+    use crate::config::{ProxyConfig, Route};
+    use warp::http::{HeaderName, StatusCode};
+    use warp::{Filter, Reply};
+
+    #[tokio::test]
+    async fn basic_and_bearer_auth() {
+        let (address, server) =
+            warp::serve(warp::any().map(warp::reply)).bind_ephemeral(([127, 0, 0, 1], 0));
+        tokio::spawn(server);
+
+        let upstream = format!("http://{}", address);
+        let filter = ProxyConfig::new(([127, 0, 0, 1], 0).into())
+            .route(Route::new("admin", &upstream).auth(Auth::basic("admin").user("ops", "secret")))
+            .route(Route::new("api", &upstream).auth(Auth::bearer("api", |token| token == "t0k")))
+            .route(Route::new("", &upstream))
+            .filter();
+        let reply = |path: &'static str, authorization: Option<&'static str>| {
+            let filter = filter.clone();
+            async move {
+                let mut request = warp::test::request().path(path);
+                if let Some(authorization) = authorization {
+                    request = request.header("authorization", authorization);
+                }
+                request.reply(&filter).await
+            }
+        };
+
+        // "ops:secret" and "ops:wrong"
+        let response = reply("/admin", Some("Basic b3BzOnNlY3JldA==")).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let response = reply("/admin/users", Some("Basic b3BzOndyb25n")).await;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        assert_eq!(
+            response.headers()["www-authenticate"],
+            "Basic realm=\"admin\""
+        );
+        assert_eq!(
+            reply("/api", Some("bearer t0k")).await.status(),
+            StatusCode::OK
+        );
+        assert_eq!(reply("/api", None).await.status(), StatusCode::UNAUTHORIZED);
+        assert_eq!(reply("/public", None).await.status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn forward_auth() {
+        let (auth, auth_server) = warp::serve(
+            warp::header::optional::<String>("cookie")
+                .and(warp::header::<String>("x-forwarded-uri"))
+                .map(
+                    |cookie: Option<String>, uri: String| match cookie.as_deref() {
+                        Some("session=42") => {
+                            warp::reply::with_header(warp::reply(), "x-user", uri).into_response()
+                        }
+                        _ => warp::reply::with_status("login", StatusCode::FOUND).into_response(),
+                    },
+                ),
+        )
+        .bind_ephemeral(([127, 0, 0, 1], 0));
+        let (address, server) = warp::serve(
+            warp::header::optional::<String>("x-user")
+                .map(|user: Option<String>| user.unwrap_or_default()),
+        )
+        .bind_ephemeral(([127, 0, 0, 1], 0));
+        tokio::spawn(auth_server);
+        tokio::spawn(server);
+
+        let filter = Route::new("", format!("http://{}", address))
+            .auth(
+                Auth::forward(format!("http://{}/verify", auth))
+                    .copy_header(HeaderName::from_static("x-user")),
+            )
+            .filter();
+        let response = warp::test::request()
+            .path("/orders?page=2")
+            .header("cookie", "session=42")
+            .header("x-user", "spoofed")
+            .reply(&filter)
+            .await;
+        assert_eq!(response.body(), "/orders?page=2");
+        let response = warp::test::request()
+            .header("x-user", "spoofed")
+            .reply(&filter)
+            .await;
+        assert_eq!(response.status(), StatusCode::FOUND);
+        assert_eq!(response.body(), "login");
+    }
+
+    #[tokio::test]
+    async fn unreachable_forward_auth_is_final() {
+        let (catchall, server) = warp::serve(
+            warp::header::optional::<String>("authorization")
+                .map(|authorization: Option<String>| authorization.unwrap_or_default()),
+        )
+        .bind_ephemeral(([127, 0, 0, 1], 0));
+        tokio::spawn(server);
+
+        let filter = ProxyConfig::new(([127, 0, 0, 1], 0).into())
+            .route(
+                Route::new("admin", format!("http://{}", catchall))
+                    .auth(Auth::forward("http://127.0.0.1:1/verify")),
+            )
+            .route(Route::new("", format!("http://{}", catchall)))
+            .filter();
+        let response = warp::test::request()
+            .path("/admin/x")
+            .header("authorization", "Bearer s3cr3t")
+            .reply(&filter)
+            .await;
+        assert_eq!(response.status(), StatusCode::BAD_GATEWAY);
+        assert!(!String::from_utf8_lossy(response.body()).contains("s3cr3t"));
+    }
+}
@@ -0,0 +1,108 @@
+//! Standalone reverse proxy serving the routes of a JSON configuration file.
+//!
+//! ```text
+//! warp-reverse-proxy --config proxy.json [--self-test]
+//! ```
+//!
+//! With `--self-test`, every upstream is probed first and the proxy exits if any fails.
+//! Built with the `unix` feature, `SIGHUP` reloads the file: requests already being served
+//! finish with the previous routes, and the listening address is kept.
+use std::process::exit;
+use warp::Filter;
+#[cfg(all(feature = "unix", unix))]
+use warp_reverse_proxy::reload;
+use warp_reverse_proxy::{config::ProxyConfig, errors};
+
+fn config_path() -> Option<String> {
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        if let Some(path) = arg.strip_prefix("--config=") {
+            return Some(path.to_string());
+        }
+        if arg == "--config" || arg == "-c" {
+            return args.next();
+        }
+    }
+    None
+}
+
+fn self_test_enabled() -> bool {
+    std::env::args().skip(1).any(|arg| arg == "--self-test")
+}
+
+#[tokio::main]
+async fn main() {
+    let path = config_path().unwrap_or_else(|| {
+        eprintln!("usage: warp-reverse-proxy --config <file> [--self-test]");
+        exit(2);
+    });
+    let config = std::fs::read_to_string(&path)
+        .map_err(|e| e.to_string())
+        .and_then(|json| ProxyConfig::from_json(&json).map_err(|e| e.to_string()))
+        .unwrap_or_else(|e| {
+            eprintln!("couldn't load configuration {}: {}", path, e);
+            exit(1);
+        });
+    let diagnostics = config.validate();
+    for diagnostic in &diagnostics {
+        eprintln!("{}: {}", path, diagnostic);
+    }
+    if diagnostics.iter().any(|diagnostic| diagnostic.is_error()) {
+        exit(1);
+    }
+    if self_test_enabled() {
+        let report = config.self_test().await;
+        eprint!("{}", report);
+        if !report.passed() {
+            exit(1);
+        }
+    }
+
+    serve(path, config).await;
+}
+
+#[cfg(not(all(feature = "unix", unix)))]
+async fn serve(_path: String, config: ProxyConfig) {
+    warp::serve(config.filter().recover(errors::recover))
+        .run(config.address())
+        .await;
+}
+
+#[cfg(all(feature = "unix", unix))]
+async fn serve(path: String, config: ProxyConfig) {
+    use futures_util::future;
+    use std::sync::Arc;
+    use tokio::net::TcpListener;
+
+    let listener = TcpListener::bind(config.address())
+        .await
+        .map(Arc::new)
+        .unwrap_or_else(|e| {
+            eprintln!("couldn't listen on {}: {}", config.address(), e);
+            exit(1);
+        });
+    let mut configs = reload::on_sighup(path, config).unwrap_or_else(|e| {
+        eprintln!("couldn't listen for SIGHUP: {}", e);
+        exit(1);
+    });
+    loop {
+        let config = configs.borrow_and_update().clone();
+        let mut reloaded = configs.clone();
+        let accepted = futures_util::stream::unfold(listener.clone(), |listener| async move {
+            let accepted = listener.accept().await.map(|(stream, _)| stream);
+            Some((accepted, listener))
+        });
+        // Dropping the accepted stream on reload hands new connections to the next server.
+        let server = warp::serve(config.filter().recover(errors::recover))
+            .serve_incoming_with_graceful_shutdown(accepted, async move {
+                if reloaded.changed().await.is_err() {
+                    future::pending::<()>().await;
+                }
+            });
+        tokio::spawn(server);
+        if configs.changed().await.is_err() {
+            // No more reloads, keep serving the current routes.
+            future::pending::<()>().await;
+        }
+    }
+}
@@ -0,0 +1,196 @@
+//! Per request data exchanged between the proxy filters and the rest of the application.
+//!
+//! Like [`ForcedUpstream`](../struct.ForcedUpstream.html), request values travel in the request
+//! extensions, inserted by a tower layer or a hyper service wrapping warp. Replies carry a
+//! [`ProxyInfo`] in their extensions for the handlers chained after the proxy filters.
+use crate::{errors, headers};
+use warp::http::{self, HeaderName, HeaderValue};
+use warp::hyper::Body;
+use warp::Filter;
+
+/// Header [`Annotations`] are sent upstream in, unless the route names another one
+pub static ANNOTATIONS_HEADER: HeaderName = HeaderName::from_static("x-proxy-context");
+
+/// Key/value annotations of a request, sent upstream as a single header.
+///
+/// They are serialized as `k1=v1,k2=v2` in insertion order, percent-encoding `%`, `,`, `=` and
+/// any byte outside visible ASCII, so they can always be decoded back.
+/// # Examples
+/// ```
+/// use warp_reverse_proxy::context::Annotations;
+///
+/// let mut annotations = Annotations::new();
+/// annotations.insert("tenant", "acme");
+/// annotations.insert("flags", "a,b");
+/// assert_eq!(annotations.header_value().unwrap(), "tenant=acme,flags=a%2Cb");
+/// ```
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Annotations(Vec<(String, String)>);
+
+impl Annotations {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds an annotation, replacing the value of an existing `key`.
+    pub fn insert(&mut self, key: impl Into<String>, value: impl Into<String>) {
+        let (key, value) = (key.into(), value.into());
+        match self.0.iter_mut().find(|(k, _)| *k == key) {
+            Some((_, v)) => *v = value,
+            None => self.0.push((key, value)),
+        }
+    }
+
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.0
+            .iter()
+            .find(|(k, _)| k == key)
+            .map(|(_, v)| v.as_str())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.0.iter().map(|(k, v)| (k.as_str(), v.as_str()))
+    }
+
+    /// Serialized header value.
+    pub fn header_value(&self) -> Result<HeaderValue, errors::Error> {
+        let value = self
+            .iter()
+            .map(|(k, v)| format!("{}={}", encode(k), encode(v)))
+            .collect::<Vec<_>>()
+            .join(",");
+        headers::checked_value(&value)
+    }
+}
+
+fn encode(value: &str) -> String {
+    value
+        .bytes()
+        .map(|b| match b {
+            b'%' | b',' | b'=' => format!("%{:02X}", b),
+            b'!'..=b'~' => (b as char).to_string(),
+            _ => format!("%{:02X}", b),
+        })
+        .collect()
+}
+
+/// How a reply was obtained, found in the extensions of the proxy filters replies.
+/// # Examples
+/// ```
+/// use warp::{http::Response, hyper::Body, Filter, Rejection, Reply};
+/// use warp_reverse_proxy::{context::ProxyInfo, reverse_proxy_filter};
+///
+/// async fn tag_replays(response: Response<Body>) -> Result<impl Reply, Rejection> {
+///     let replayed = response
+///         .extensions()
+///         .get::<ProxyInfo>()
+///         .is_some_and(|info| info.replayed);
+///     Ok(warp::reply::with_header(response, "x-replayed", replayed.to_string()))
+/// }
+///
+/// let app = reverse_proxy_filter("".to_string(), "http://127.0.0.1:8080".to_string())
+///     .and_then(tag_replays);
+/// ```
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct ProxyInfo {
+    /// Upstream URI the request was forwarded to, without query parameters
+    pub upstream: String,
+    /// Whether the reply is a stored one, replayed without reaching the upstream
+    pub replayed: bool,
+}
+
+impl ProxyInfo {
+    pub(crate) fn new(upstream: &str) -> Self {
+        Self {
+            upstream: upstream.to_string(),
+            replayed: false,
+        }
+    }
+
+    pub(crate) fn attach(self, mut reply: http::Response<Body>) -> http::Response<Body> {
+        reply.extensions_mut().insert(self);
+        reply
+    }
+}
+
+/// Request extensions the forwarding pipeline acts on.
+#[derive(Debug, Default)]
+pub(crate) struct RequestContext {
+    pub(crate) annotations: Option<Annotations>,
+    /// Streamed request body, sent instead of the buffered one
+    pub(crate) body: Option<reqwest::Body>,
+    /// Bytes of the streamed body read so far, when the route counts its traffic
+    pub(crate) streamed_bytes: Option<std::sync::Arc<std::sync::atomic::AtomicU64>>,
+}
+
+pub(crate) fn filter(
+) -> impl Filter<Extract = (RequestContext,), Error = std::convert::Infallible> + Clone {
+    warp::ext::optional::<Annotations>().map(|annotations| RequestContext {
+        annotations,
+        body: None,
+        streamed_bytes: None,
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Annotations, ProxyInfo};
+    use crate::reverse_proxy_filter;
+    use warp::Filter;
+
+    #[test]
+    fn annotations_are_encoded() {
+        let mut annotations = Annotations::new();
+        annotations.insert("k 1", "caf\u{e9}");
+        annotations.insert("k2", "50%=half");
+        annotations.insert("k 1", "v1");
+        annotations.insert("k3", "caf\u{e9}");
+        assert_eq!(annotations.get("k 1"), Some("v1"));
+        assert_eq!(
+            annotations.header_value().unwrap(),
+            "k%201=v1,k2=50%25%3Dhalf,k3=caf%C3%A9"
+        );
+    }
+
+    #[tokio::test]
+    async fn annotations_are_forwarded() {
+        let (address, server) = warp::serve(
+            warp::header::optional::<String>("x-proxy-context")
+                .map(|context: Option<String>| context.unwrap_or_default()),
+        )
+        .bind_ephemeral(([127, 0, 0, 1], 0));
+        tokio::spawn(server);
+
+        let mut annotations = Annotations::new();
+        annotations.insert("tenant", "acme");
+        annotations.insert("region", "eu");
+        let filter = reverse_proxy_filter("".to_string(), format!("http://{}", address));
+        let response = warp::test::request()
+            .extension(annotations)
+            .reply(&filter)
+            .await;
+        assert_eq!(response.body(), "tenant=acme,region=eu");
+    }
+
+    #[tokio::test]
+    async fn replies_carry_proxy_info() {
+        let (address, server) =
+            warp::serve(warp::any().map(warp::reply)).bind_ephemeral(([127, 0, 0, 1], 0));
+        tokio::spawn(server);
+
+        let filter = reverse_proxy_filter("foo".to_string(), format!("http://{}", address));
+        let response = warp::test::request()
+            .path("/foo/bar?baz")
+            .filter(&filter)
+            .await
+            .unwrap();
+        let info = response.extensions().get::<ProxyInfo>().unwrap();
+        assert_eq!(info.upstream, format!("http://{}/bar", address));
+        assert!(!info.replayed);
+    }
+}
@@ -0,0 +1,102 @@
+//! Assertions on upstream responses, enforcing their contract at the gateway.
+use crate::errors;
+use warp::http::{HeaderMap, HeaderName, StatusCode};
+
+/// Properties every upstream response of a route must have.
+///
+/// Responses breaking it are logged with the violation details and replaced with
+/// [`errors::Error::UpstreamStatus`] for an unexpected status, or
+/// [`errors::Error::ContractViolation`] for other violations, replied as `502 Bad Gateway` by
+/// the route.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ResponseContract {
+    statuses: Option<Vec<StatusCode>>,
+    required_headers: Vec<HeaderName>,
+}
+
+impl ResponseContract {
+    /// Contract accepting any response, to be narrowed with the setters.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Only accepts responses with one of `statuses`.
+    pub fn statuses<I>(mut self, statuses: I) -> Self
+    where
+        I: IntoIterator<Item = StatusCode>,
+    {
+        self.statuses = Some(statuses.into_iter().collect());
+        self
+    }
+
+    /// Only accepts responses carrying `header`.
+    pub fn required_header(mut self, header: HeaderName) -> Self {
+        self.required_headers.push(header);
+        self
+    }
+
+    /// Checks a response status and headers against the contract.
+    pub fn check(&self, status: StatusCode, headers: &HeaderMap) -> Result<(), errors::Error> {
+        if let Some(statuses) = &self.statuses {
+            if !statuses.contains(&status) {
+                return Err(errors::Error::UpstreamStatus(status));
+            }
+        }
+        match self
+            .required_headers
+            .iter()
+            .find(|header| !headers.contains_key(*header))
+        {
+            Some(header) => Err(errors::Error::ContractViolation(format!(
+                "missing header {}",
+                header
+            ))),
+            None => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::ResponseContract;
+    use crate::config::Route;
+    use crate::errors;
+    use warp::http::{HeaderMap, HeaderName, HeaderValue, StatusCode};
+    use warp::Filter;
+
+    #[test]
+    fn contract_checks_status_and_headers() {
+        let contract = ResponseContract::new()
+            .statuses(vec![StatusCode::OK, StatusCode::NO_CONTENT])
+            .required_header(HeaderName::from_static("content-type"));
+        let mut headers = HeaderMap::new();
+        assert!(contract.check(StatusCode::OK, &headers).is_err());
+        headers.insert("content-type", HeaderValue::from_static("text/plain"));
+        assert!(contract.check(StatusCode::NO_CONTENT, &headers).is_ok());
+        assert!(matches!(
+            contract.check(StatusCode::CREATED, &headers),
+            Err(errors::Error::UpstreamStatus(StatusCode::CREATED))
+        ));
+        assert!(ResponseContract::new()
+            .check(StatusCode::INTERNAL_SERVER_ERROR, &HeaderMap::new())
+            .is_ok());
+    }
+
+    #[tokio::test]
+    async fn contract_violations_reply_502() {
+        let (address, server) = warp::serve(
+            warp::path!("created").map(|| warp::reply::with_status("created", StatusCode::CREATED)),
+        )
+        .bind_ephemeral(([127, 0, 0, 1], 0));
+        tokio::spawn(server);
+
+        let filter = Route::new("", format!("http://{}", address))
+            .response_contract(ResponseContract::new().statuses(vec![StatusCode::CREATED]))
+            .filter()
+            .recover(crate::errors::recover);
+        let response = warp::test::request().path("/created").reply(&filter).await;
+        assert_eq!(response.status(), StatusCode::CREATED);
+        let response = warp::test::request().path("/missing").reply(&filter).await;
+        assert_eq!(response.status(), StatusCode::BAD_GATEWAY);
+    }
+}
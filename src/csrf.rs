@@ -0,0 +1,231 @@
+//! Cross-site request forgery protection for upstreams that have none.
+//!
+//! Only state-changing requests are checked: `GET`, `HEAD`, `OPTIONS` and `TRACE` always pass.
+use crate::{audit, constant_time_eq, errors, headers};
+use warp::filters::path::FullPath;
+use warp::http::{header, HeaderMap, HeaderName, Method, Uri};
+use warp::{Filter, Rejection};
+
+/// CSRF check of a route.
+///
+/// Refused requests fail with [`errors::Error::CsrfRejected`], replied as `403 Forbidden` by
+/// the [`Route`](../config/struct.Route.html#method.csrf) it is set on, never by the next routes.
+/// The standalone [`filter`](#method.filter) rejects them instead, for
+/// [`errors::recover`](../errors/fn.recover.html) to reply.
+/// # Examples
+/// ```
+/// use warp::http::HeaderName;
+/// use warp_reverse_proxy::config::Route;
+/// use warp_reverse_proxy::csrf::Csrf;
+///
+/// let forms = Route::new("forms", "http://legacy.local")
+///     .csrf(Csrf::origin().trusted_origin("https://app.example.com"));
+/// let api = Route::new("api", "http://legacy.local").csrf(Csrf::double_submit(
+///     "csrf_token",
+///     HeaderName::from_static("x-csrf-token"),
+/// ));
+/// ```
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Csrf(Check);
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum Check {
+    Origin(Vec<String>),
+    DoubleSubmit(String, HeaderName),
+}
+
+impl Csrf {
+    /// Requires the `Origin` of requests, or their `Referer` when they have none, to be the
+    /// requested host or a [`trusted_origin`](#method.trusted_origin).
+    ///
+    /// Requests with neither header are refused. The scheme of the requested host isn't known
+    /// to the proxy, so same host origins pass whatever their scheme.
+    pub fn origin() -> Self {
+        Self(Check::Origin(Vec::new()))
+    }
+
+    /// Requires requests to repeat the value of the `cookie` cookie in the `header` header,
+    /// which cross-site pages can't read. The cookie is set by the upstream or the application.
+    pub fn double_submit(cookie: impl Into<String>, header: HeaderName) -> Self {
+        Self(Check::DoubleSubmit(cookie.into(), header))
+    }
+
+    /// Accepts requests from `origin`, such as `https://app.example.com`, with an origin check.
+    pub fn trusted_origin(mut self, origin: impl Into<String>) -> Self {
+        if let Check::Origin(trusted) = &mut self.0 {
+            trusted.push(origin.into().trim_end_matches('/').to_string());
+        }
+        self
+    }
+
+    /// Checks a request method and headers.
+    pub fn check(&self, method: &Method, headers: &HeaderMap) -> Result<(), errors::Error> {
+        if matches!(
+            *method,
+            Method::GET | Method::HEAD | Method::OPTIONS | Method::TRACE
+        ) {
+            return Ok(());
+        }
+        match &self.0 {
+            Check::Origin(trusted) => check_origin(trusted, headers),
+            Check::DoubleSubmit(cookie, header) => {
+                let expected = headers::cookie(headers, cookie)
+                    .filter(|token| !token.is_empty())
+                    .ok_or(errors::Error::CsrfRejected("missing token cookie"))?;
+                let token = headers
+                    .get(header)
+                    .ok_or(errors::Error::CsrfRejected("missing token header"))?;
+                match constant_time_eq(expected.as_bytes(), token.as_bytes()) {
+                    true => Ok(()),
+                    false => Err(errors::Error::CsrfRejected("token mismatch")),
+                }
+            }
+        }
+    }
+
+    /// Warp filter rejecting the requests failing the check.
+    pub fn filter(self) -> impl Filter<Extract = (), Error = Rejection> + Clone {
+        audit::request_line()
+            .and(warp::header::headers_cloned())
+            .and_then(move |method: Method, path: FullPath, headers: HeaderMap| {
+                let result = self.check(&method, &headers).map_err(|e| {
+                    warp::reject::custom(audit::denied(&method, path.as_str(), "csrf", e))
+                });
+                async move { result }
+            })
+            .untuple_one()
+    }
+}
+
+fn check_origin(trusted: &[String], headers: &HeaderMap) -> Result<(), errors::Error> {
+    let origin = match headers.get(header::ORIGIN) {
+        Some(origin) => origin.to_str().ok().map(str::to_string),
+        None => headers
+            .get(header::REFERER)
+            .ok_or(errors::Error::CsrfRejected("missing Origin and Referer"))?
+            .to_str()
+            .ok()
+            .and_then(|referer| referer.parse::<Uri>().ok())
+            .and_then(|referer| Some(format!("{}://{}", referer.scheme()?, referer.authority()?))),
+    }
+    .ok_or(errors::Error::CsrfRejected("invalid origin"))?;
+    if trusted.iter().any(|t| t.eq_ignore_ascii_case(&origin)) {
+        return Ok(());
+    }
+    let host = headers
+        .get(header::HOST)
+        .and_then(|host| host.to_str().ok());
+    let authority = origin.split_once("://").map(|(_, authority)| authority);
+    match (host, authority) {
+        (Some(host), Some(authority)) if host.eq_ignore_ascii_case(authority) => Ok(()),
+        _ => Err(errors::Error::CsrfRejected("cross-site origin")),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Csrf;
+    use crate::config::{ProxyConfig, Route};
+    use warp::http::{HeaderMap, HeaderName, HeaderValue, Method, StatusCode};
+    use warp::Filter;
+
+    fn headers(headers: &[(&'static str, &'static str)]) -> HeaderMap {
+        headers
+            .iter()
+            .map(|(name, value)| {
+                (
+                    HeaderName::from_static(name),
+                    HeaderValue::from_static(value),
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn origin_checks() {
+        let csrf = Csrf::origin().trusted_origin("https://app.example.com/");
+        let check = |method: Method, list: &[(&'static str, &'static str)]| {
+            csrf.check(&method, &headers(list)).is_ok()
+        };
+        let host = ("host", "shop.example.com");
+        assert!(check(Method::GET, &[]));
+        assert!(!check(Method::POST, &[host]));
+        assert!(check(
+            Method::POST,
+            &[host, ("origin", "https://shop.example.com")]
+        ));
+        assert!(check(
+            Method::POST,
+            &[host, ("origin", "https://APP.example.com")]
+        ));
+        assert!(!check(
+            Method::DELETE,
+            &[host, ("origin", "https://evil.com")]
+        ));
+        assert!(!check(Method::POST, &[host, ("origin", "null")]));
+        assert!(check(
+            Method::PUT,
+            &[host, ("referer", "https://shop.example.com/cart?id=1")]
+        ));
+        assert!(!check(
+            Method::PUT,
+            &[host, ("referer", "https://evil.com/shop.example.com")]
+        ));
+    }
+
+    #[tokio::test]
+    async fn double_submit_tokens() {
+        let (address, server) =
+            warp::serve(warp::any().map(warp::reply)).bind_ephemeral(([127, 0, 0, 1], 0));
+        tokio::spawn(server);
+
+        let filter = Route::new("", format!("http://{}", address))
+            .csrf(Csrf::double_submit(
+                "csrf",
+                HeaderName::from_static("x-csrf-token"),
+            ))
+            .filter()
+            .recover(crate::errors::recover);
+        let reply = |cookie: &'static str, token: Option<&'static str>| {
+            let filter = filter.clone();
+            async move {
+                let mut request = warp::test::request()
+                    .method("POST")
+                    .header("cookie", cookie);
+                if let Some(token) = token {
+                    request = request.header("x-csrf-token", token);
+                }
+                request.reply(&filter).await.status()
+            }
+        };
+        assert_eq!(
+            reply("csrf=abc; theme=dark", Some("abc")).await,
+            StatusCode::OK
+        );
+        assert_eq!(reply("csrf=abc", Some("abd")).await, StatusCode::FORBIDDEN);
+        assert_eq!(reply("csrf=abc", None).await, StatusCode::FORBIDDEN);
+        assert_eq!(reply("csrf=", Some("")).await, StatusCode::FORBIDDEN);
+    }
+
+    #[tokio::test]
+    async fn refusals_skip_fallback_routes() {
+        let (address, server) =
+            warp::serve(warp::any().map(|| "catchall")).bind_ephemeral(([127, 0, 0, 1], 0));
+        tokio::spawn(server);
+        let upstream = format!("http://{}", address);
+
+        let filter = ProxyConfig::new(([127, 0, 0, 1], 0).into())
+            .route(Route::new("admin", &upstream).csrf(Csrf::origin()))
+            .route(Route::new("", &upstream))
+            .filter();
+        let response = warp::test::request()
+            .method("POST")
+            .path("/admin/x")
+            .header("host", "admin.example.com")
+            .body("delete=all")
+            .reply(&filter)
+            .await;
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+        assert_ne!(response.body(), "catchall");
+    }
+}
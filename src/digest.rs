@@ -0,0 +1,226 @@
+//! `Digest` header (RFC 3230) and `Content-MD5` (RFC 1864) verification and generation for
+//! request and response bodies.
+//!
+//! The `SHA` (SHA-1) and `MD5` `Digest` algorithms are supported. Entries using other
+//! algorithms are left unchecked, but a request with a body must carry at least one supported
+//! entry or a `Content-MD5` header to be verified.
+use crate::errors;
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use sha1::{Digest as _, Sha1};
+use warp::http::{HeaderMap, HeaderName, HeaderValue};
+use warp::hyper::body::Bytes;
+
+pub(crate) static DIGEST: HeaderName = HeaderName::from_static("digest");
+pub(crate) static CONTENT_MD5: HeaderName = HeaderName::from_static("content-md5");
+
+/// `Digest` header value for a body.
+pub(crate) fn value(body: &Bytes) -> HeaderValue {
+    let digest = STANDARD.encode(Sha1::digest(body));
+    HeaderValue::from_str(&format!("SHA={}", digest)).expect("base64 is a valid header value")
+}
+
+/// HMAC-SHA1 (RFC 2104) of `message` keyed with `key`.
+pub(crate) fn hmac_sha1(key: &[u8], message: &[u8]) -> [u8; 20] {
+    const BLOCK: usize = 64;
+    let mut block = [0u8; BLOCK];
+    if key.len() > BLOCK {
+        block[..20].copy_from_slice(&Sha1::digest(key));
+    } else {
+        block[..key.len()].copy_from_slice(key);
+    }
+    let pad = |byte: u8| block.iter().map(move |b| b ^ byte).collect::<Vec<_>>();
+    let inner = Sha1::new()
+        .chain_update(pad(0x36))
+        .chain_update(message)
+        .finalize();
+    Sha1::new()
+        .chain_update(pad(0x5c))
+        .chain_update(inner)
+        .finalize()
+        .into()
+}
+
+/// MD5 (RFC 1321) of `message`.
+pub(crate) fn md5(message: &[u8]) -> [u8; 16] {
+    const SHIFTS: [u32; 16] = [7, 12, 17, 22, 5, 9, 14, 20, 4, 11, 16, 23, 6, 10, 15, 21];
+    let constants: Vec<u32> = (0..64)
+        .map(|i| ((i as f64 + 1.0).sin().abs() * 4_294_967_296.0) as u32)
+        .collect();
+    let mut state: [u32; 4] = [0x6745_2301, 0xefcd_ab89, 0x98ba_dcfe, 0x1032_5476];
+    let mut padded = message.to_vec();
+    padded.push(0x80);
+    while padded.len() % 64 != 56 {
+        padded.push(0);
+    }
+    padded.extend_from_slice(&((message.len() as u64).wrapping_mul(8)).to_le_bytes());
+    for chunk in padded.chunks_exact(64) {
+        let words: Vec<u32> = chunk
+            .chunks_exact(4)
+            .map(|word| u32::from_le_bytes([word[0], word[1], word[2], word[3]]))
+            .collect();
+        let [mut a, mut b, mut c, mut d] = state;
+        for i in 0..64 {
+            let (f, g) = match i / 16 {
+                0 => ((b & c) | (!b & d), i),
+                1 => ((d & b) | (!d & c), (5 * i + 1) % 16),
+                2 => (b ^ c ^ d, (3 * i + 5) % 16),
+                _ => (c ^ (b | !d), (7 * i) % 16),
+            };
+            let rotated = a
+                .wrapping_add(f)
+                .wrapping_add(constants[i])
+                .wrapping_add(words[g])
+                .rotate_left(SHIFTS[(i / 16) * 4 + i % 4]);
+            a = d;
+            d = c;
+            c = b;
+            b = b.wrapping_add(rotated);
+        }
+        for (word, value) in state.iter_mut().zip([a, b, c, d]) {
+            *word = word.wrapping_add(value);
+        }
+    }
+    let mut digest = [0u8; 16];
+    for (bytes, word) in digest.chunks_exact_mut(4).zip(state) {
+        bytes.copy_from_slice(&word.to_le_bytes());
+    }
+    digest
+}
+
+/// Checks every supported `Digest` entry and `Content-MD5` header against the body.
+///
+/// Requests with a body and none of them fail: they can't be verified.
+pub(crate) fn verify(headers: &HeaderMap, body: &Bytes) -> Result<(), errors::Error> {
+    let mut md5_digest = None;
+    let mut md5_digest = || -> String {
+        md5_digest
+            .get_or_insert_with(|| STANDARD.encode(md5(body)))
+            .clone()
+    };
+    let mut sha = None;
+    let mut checked = false;
+    for value in headers.get_all(&DIGEST) {
+        let value = value
+            .to_str()
+            .map_err(|_| errors::Error::DigestMismatch("unreadable Digest header"))?;
+        for entry in value.split(',') {
+            let (algorithm, digest) = match entry.trim().split_once('=') {
+                Some(entry) => entry,
+                None => return Err(errors::Error::DigestMismatch("malformed Digest header")),
+            };
+            let expected = match algorithm {
+                _ if algorithm.eq_ignore_ascii_case("SHA") => sha
+                    .get_or_insert_with(|| STANDARD.encode(Sha1::digest(body)))
+                    .clone(),
+                _ if algorithm.eq_ignore_ascii_case("MD5") => md5_digest(),
+                _ => continue,
+            };
+            if digest.trim() != expected {
+                return Err(errors::Error::DigestMismatch(
+                    "digest does not match the body",
+                ));
+            }
+            checked = true;
+        }
+    }
+    for value in headers.get_all(&CONTENT_MD5) {
+        if value.to_str().ok().map(str::trim) != Some(md5_digest().as_str()) {
+            return Err(errors::Error::DigestMismatch(
+                "Content-MD5 does not match the body",
+            ));
+        }
+        checked = true;
+    }
+    match checked || body.is_empty() {
+        true => Ok(()),
+        false => Err(errors::Error::DigestMismatch(
+            "no SHA or MD5 digest of the body",
+        )),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{hmac_sha1, md5, value, verify, CONTENT_MD5, DIGEST};
+    use warp::http::{HeaderMap, HeaderValue};
+    use warp::hyper::body::Bytes;
+
+    #[test]
+    fn digest_round_trip() {
+        let body = Bytes::from("foo bar");
+        let mut headers = HeaderMap::new();
+        assert!(verify(&headers, &Bytes::new()).is_ok());
+        assert!(verify(&headers, &body).is_err());
+
+        headers.insert(&DIGEST, value(&body));
+        assert_eq!(headers[&DIGEST], "SHA=N3PeplFWkJg4+mwiglyv4JD/gDA=");
+        assert!(verify(&headers, &body).is_ok());
+        assert!(verify(&headers, &Bytes::from("foo baz")).is_err());
+
+        headers.insert(
+            &DIGEST,
+            HeaderValue::from_static("SHA-256=unchecked, sha=N3PeplFWkJg4+mwiglyv4JD/gDA="),
+        );
+        assert!(verify(&headers, &body).is_ok());
+        headers.insert(&DIGEST, HeaderValue::from_static("SHA-256=unchecked"));
+        assert!(verify(&headers, &body).is_err());
+        headers.insert(&DIGEST, HeaderValue::from_static("SHA-256"));
+        assert!(verify(&headers, &body).is_err());
+
+        headers.remove(&DIGEST);
+        headers.insert(
+            &CONTENT_MD5,
+            HeaderValue::from_static("MntvB0NYESObxH4VRDUycw=="),
+        );
+        assert!(verify(&headers, &body).is_ok());
+        headers.insert(
+            &DIGEST,
+            HeaderValue::from_static("md5=MntvB0NYESObxH4VRDUycw=="),
+        );
+        assert!(verify(&headers, &body).is_ok());
+        headers.insert(&CONTENT_MD5, HeaderValue::from_static("garbage"));
+        assert!(verify(&headers, &body).is_err());
+    }
+
+    #[test]
+    fn md5_vectors() {
+        // RFC 1321 test suite
+        let hex = |bytes: [u8; 16]| {
+            bytes
+                .iter()
+                .map(|b| format!("{:02x}", b))
+                .collect::<String>()
+        };
+        assert_eq!(hex(md5(b"")), "d41d8cd98f00b204e9800998ecf8427e");
+        assert_eq!(hex(md5(b"abc")), "900150983cd24fb0d6963f7d28e17f72");
+        assert_eq!(
+            hex(md5(
+                b"12345678901234567890123456789012345678901234567890123456789012345678901234567890"
+            )),
+            "57edf4a22be3c955ac49da2e2107b67a"
+        );
+    }
+
+    #[test]
+    fn hmac_sha1_vectors() {
+        // RFC 2202 test cases 2 and 6
+        let hex = |bytes: [u8; 20]| {
+            bytes
+                .iter()
+                .map(|b| format!("{:02x}", b))
+                .collect::<String>()
+        };
+        assert_eq!(
+            hex(hmac_sha1(b"Jefe", b"what do ya want for nothing?")),
+            "effcdf6ae5eb2fa2d27416d5f184df9c259a7c79"
+        );
+        assert_eq!(
+            hex(hmac_sha1(
+                &[0xaa; 80],
+                b"Test Using Larger Than Block-Size Key - Hash Key First"
+            )),
+            "aa4ae5e15272d00e95705637ce8a3b55ed402112"
+        );
+    }
+}
@@ -0,0 +1,63 @@
+//! Name resolution for route upstreams.
+use crate::config::IpPreference;
+use reqwest::dns::{Addrs, Resolve, Resolving};
+use std::net::SocketAddr;
+use warp::hyper::client::connect::dns::Name;
+
+/// System resolver of the proxy clients, ordering (or filtering) the addresses by IP family
+/// when a route has a preference.
+///
+/// hyper connects to the family of the first address and races the other one after a short
+/// delay, so ordering is enough to express a preference while keeping Happy Eyeballs.
+pub(crate) struct FamilyResolver(pub(crate) Option<IpPreference>);
+
+/// Resolution failure, found in the source chain of the client errors to tell DNS failures
+/// from other connect ones.
+#[derive(Debug, thiserror::Error)]
+#[error(transparent)]
+pub(crate) struct ResolveError(#[from] std::io::Error);
+
+impl Resolve for FamilyResolver {
+    fn resolve(&self, name: Name) -> Resolving {
+        let preference = self.0;
+        Box::pin(async move {
+            let addrs = tokio::net::lookup_host((name.as_str(), 0))
+                .await
+                .map_err(ResolveError)?;
+            let addrs = match preference {
+                Some(preference) => order(addrs.collect(), preference),
+                None => addrs.collect(),
+            };
+            let addrs: Addrs = Box::new(addrs.into_iter());
+            Ok(addrs)
+        })
+    }
+}
+
+fn order(addrs: Vec<SocketAddr>, preference: IpPreference) -> Vec<SocketAddr> {
+    let (v4, v6): (Vec<_>, Vec<_>) = addrs.into_iter().partition(SocketAddr::is_ipv4);
+    match preference {
+        IpPreference::PreferV4 => v4.into_iter().chain(v6).collect(),
+        IpPreference::PreferV6 => v6.into_iter().chain(v4).collect(),
+        IpPreference::V4Only => v4,
+        IpPreference::V6Only => v6,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::order;
+    use crate::config::IpPreference;
+    use std::net::SocketAddr;
+
+    #[test]
+    fn addresses_ordered_by_family() {
+        let v4: SocketAddr = ([127, 0, 0, 1], 0).into();
+        let v6: SocketAddr = "[::1]:0".parse().unwrap();
+        let addrs = vec![v6, v4];
+        assert_eq!(order(addrs.clone(), IpPreference::PreferV4), vec![v4, v6]);
+        assert_eq!(order(addrs.clone(), IpPreference::PreferV6), vec![v6, v4]);
+        assert_eq!(order(addrs.clone(), IpPreference::V4Only), vec![v4]);
+        assert_eq!(order(addrs, IpPreference::V6Only), vec![v6]);
+    }
+}
@@ -1,9 +1,19 @@
+use crate::dns::ResolveError;
+use std::time::Duration;
 use thiserror::Error;
+use warp::http::{self, header, HeaderValue, StatusCode};
+use warp::hyper::Body;
 use warp::reject::Reject;
+use warp::{Rejection, Reply};
 
 /// Lib errors wrapper
 /// Encapsulates the different errors that can occur during forwarding requests
+///
+/// New variants can be added in minor releases, so matches need a wildcard arm; branch on
+/// [`Error::kind`] instead to handle failure modes. There is no attempt accessor next to
+/// [`Error::upstream`]: the proxy sends each request upstream once, never retrying it.
 #[derive(Error, Debug)]
+#[non_exhaustive]
 pub enum Error {
     /// Errors produced by reading or building requests
     #[error("Request error: {0}")]
@@ -12,6 +22,282 @@ pub enum Error {
     /// Errors when connecting to the target service
     #[error("Http error: {0}")]
     Http(#[from] warp::http::Error),
+
+    /// A streamed response went silent for longer than the configured idle timeout
+    #[error("Idle timeout of {0:?} elapsed")]
+    IdleTimeout(Duration),
+
+    /// The proxy is draining and does not take new requests
+    #[error("Proxy is shutting down")]
+    ShuttingDown,
+
+    /// Invalid proxy configuration
+    #[error("Configuration error: {0}")]
+    Config(String),
+
+    /// Requests whose body length could be read differently by the upstream, a smuggling vector
+    #[error("Ambiguous request framing: {0}")]
+    AmbiguousFraming(&'static str),
+
+    /// Request headers exceeding the configured limits
+    #[error("Request headers too large: {0}")]
+    HeadersTooLarge(String),
+
+    /// The upstream URI would be longer than the configured maximum
+    #[error("Upstream URI longer than {0} bytes")]
+    UriTooLong(usize),
+
+    /// A header value built by the proxy contains forbidden bytes
+    #[error("Invalid header value: {0}")]
+    InvalidHeaderValue(String),
+
+    /// The request body doesn't match its `Digest` header
+    #[error("Digest mismatch: {0}")]
+    DigestMismatch(&'static str),
+
+    /// Signed URL that is expired, tampered with or not signed
+    #[error("Invalid signed URL: {0}")]
+    InvalidSignature(&'static str),
+
+    /// A method the route doesn't forward, with the `Allow` header value listing the ones it does
+    #[error("Method not allowed, allowed methods: {0}")]
+    MethodNotAllowed(String),
+
+    /// A state-changing request failing the route [`Csrf`](../csrf/struct.Csrf.html) checks
+    #[error("Cross-site request refused: {0}")]
+    CsrfRejected(&'static str),
+
+    /// An `Idempotency-Key` already used for a request with a different body
+    #[error("Idempotency key reused with a different request body")]
+    IdempotencyKeyReused,
+
+    /// A request body whose media type the route [`ContentTypes`](../media/struct.ContentTypes.html) don't allow
+    #[error("Unsupported media type: {0}")]
+    UnsupportedMediaType(String),
+
+    /// A WebSocket connection to the upstream that couldn't be established
+    #[error("WebSocket upstream error: {0}")]
+    WebSocket(String),
+
+    /// An upstream response breaking the route [`ResponseContract`](../contract/struct.ResponseContract.html)
+    #[error("Upstream response contract violation: {0}")]
+    ContractViolation(String),
+
+    /// An upstream response status the route [`ResponseContract`](../contract/struct.ResponseContract.html) doesn't accept
+    #[error("Unexpected upstream status {}", .0.as_u16())]
+    UpstreamStatus(StatusCode),
+
+    /// A request body larger than the route [`BodyLimit`](../limits/struct.BodyLimit.html), in bytes
+    #[error("Request body larger than {0} bytes")]
+    BodyTooLarge(u64),
+}
+
+/// Failure modes of an [`Error`], for recover filters and observers to branch on.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum ErrorKind {
+    /// The upstream host name didn't resolve
+    Dns,
+    /// No connection to the upstream could be established, TLS handshake included
+    Connect,
+    /// Connecting to the upstream took longer than the client connect timeout
+    ConnectTimeout,
+    /// The upstream didn't reply, or a streamed response went silent, in time
+    Timeout,
+    /// The upstream connection failed while exchanging the request or the response
+    Upstream,
+    /// The upstream response broke the route contract
+    ContractViolation,
+    /// The proxy refused the request by itself, as reported to the [`audit`](../audit/index.html) stream
+    Refused,
+    /// The proxy is draining and does not take new requests
+    ShuttingDown,
+    /// Proxy misconfiguration, or a request or reply it couldn't build
+    Internal,
+}
+
+impl Error {
+    /// Failure mode of the error.
+    pub fn kind(&self) -> ErrorKind {
+        match self {
+            Error::Request(e) if body_too_large(e) => ErrorKind::Refused,
+            Error::Request(e) if e.is_connect() && e.is_timeout() => ErrorKind::ConnectTimeout,
+            Error::Request(e) if e.is_connect() && source::<ResolveError>(e).is_some() => {
+                ErrorKind::Dns
+            }
+            Error::Request(e) if e.is_connect() => ErrorKind::Connect,
+            Error::Request(e) if e.is_timeout() => ErrorKind::Timeout,
+            Error::Request(e) if e.is_builder() => ErrorKind::Internal,
+            Error::Request(_) => ErrorKind::Upstream,
+            Error::WebSocket(_) => ErrorKind::Connect,
+            Error::IdleTimeout(_) => ErrorKind::Timeout,
+            Error::ContractViolation(_) | Error::UpstreamStatus(_) => ErrorKind::ContractViolation,
+            Error::ShuttingDown => ErrorKind::ShuttingDown,
+            Error::Http(_) | Error::Config(_) | Error::InvalidHeaderValue(_) => ErrorKind::Internal,
+            Error::AmbiguousFraming(_)
+            | Error::HeadersTooLarge(_)
+            | Error::UriTooLong(_)
+            | Error::DigestMismatch(_)
+            | Error::InvalidSignature(_)
+            | Error::CsrfRejected(_)
+            | Error::UnsupportedMediaType(_)
+            | Error::IdempotencyKeyReused
+            | Error::BodyTooLarge(_)
+            | Error::MethodNotAllowed(_) => ErrorKind::Refused,
+        }
+    }
+
+    /// Upstream URL of the failed exchange, when the error comes from one.
+    pub fn upstream(&self) -> Option<&reqwest::Url> {
+        match self {
+            Error::Request(e) => e.url(),
+            _ => None,
+        }
+    }
+
+    /// Status code replied to the client when a request fails with this error.
+    pub fn status_code(&self) -> StatusCode {
+        match self {
+            Error::Request(e) if body_too_large(e) => StatusCode::PAYLOAD_TOO_LARGE,
+            Error::Request(e) if e.is_timeout() => StatusCode::GATEWAY_TIMEOUT,
+            Error::Request(_)
+            | Error::Http(_)
+            | Error::WebSocket(_)
+            | Error::ContractViolation(_)
+            | Error::UpstreamStatus(_) => StatusCode::BAD_GATEWAY,
+            Error::IdleTimeout(_) => StatusCode::GATEWAY_TIMEOUT,
+            Error::ShuttingDown => StatusCode::SERVICE_UNAVAILABLE,
+            Error::Config(_) | Error::InvalidHeaderValue(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            Error::AmbiguousFraming(_) | Error::DigestMismatch(_) => StatusCode::BAD_REQUEST,
+            Error::HeadersTooLarge(_) => StatusCode::REQUEST_HEADER_FIELDS_TOO_LARGE,
+            Error::UriTooLong(_) => StatusCode::URI_TOO_LONG,
+            Error::InvalidSignature(_) | Error::CsrfRejected(_) => StatusCode::FORBIDDEN,
+            Error::MethodNotAllowed(_) => StatusCode::METHOD_NOT_ALLOWED,
+            Error::UnsupportedMediaType(_) => StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            Error::IdempotencyKeyReused => StatusCode::UNPROCESSABLE_ENTITY,
+            Error::BodyTooLarge(_) => StatusCode::PAYLOAD_TOO_LARGE,
+        }
+    }
+}
+
+/// First error of type `T` in the source chain of a client error.
+///
+/// Resolution failures are only found as [`ResolveError`] with the clients built by the proxy,
+/// so those of a client set as [`CLIENT`](../static.CLIENT.html) are classified as
+/// [`ErrorKind::Connect`].
+fn source<T: std::error::Error + 'static>(error: &reqwest::Error) -> Option<&T> {
+    let mut source = std::error::Error::source(error);
+    while let Some(error) = source {
+        if let Some(found) = error.downcast_ref::<T>() {
+            return Some(found);
+        }
+        source = error.source();
+    }
+    None
+}
+
+/// Whether a streamed request body failed its route [`BodyLimit`](../limits/struct.BodyLimit.html).
+fn body_too_large(error: &reqwest::Error) -> bool {
+    matches!(source::<Error>(error), Some(Error::BodyTooLarge(_)))
 }
 
 impl Reject for Error {}
+
+/// Rejection handler replying lib errors with their [`status_code`](enum.Error.html#method.status_code).
+///
+/// Messages are redacted with the [`REDACTION`](../redact/static.REDACTION.html) rules, and
+/// [`Error::MethodNotAllowed`] replies carry the `Allow` header. The reply extensions hold the
+/// error [`ErrorKind`]. Other rejections are passed through.
+/// # Examples
+/// ```
+/// use warp::Filter;
+/// use warp_reverse_proxy::{errors, reverse_proxy_filter};
+///
+/// let app = reverse_proxy_filter("".to_string(), "http://127.0.0.1:8080/".to_string())
+///     .recover(errors::recover);
+/// ```
+pub async fn recover(rejection: Rejection) -> Result<impl Reply, Rejection> {
+    match rejection.find::<Error>() {
+        Some(error) => Ok(reply(error)),
+        None => Err(rejection),
+    }
+}
+
+/// Reply to a request failing with `error`, as built by [`recover`].
+pub(crate) fn reply(error: &Error) -> http::Response<Body> {
+    let message = crate::redact::current().uri(&error.to_string());
+    let mut reply = warp::reply::with_status(message, error.status_code()).into_response();
+    reply.extensions_mut().insert(error.kind());
+    if let Error::MethodNotAllowed(allow) = error {
+        if let Ok(allow) = HeaderValue::from_str(allow) {
+            reply.headers_mut().insert(header::ALLOW, allow);
+        }
+    }
+    reply
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Error, ErrorKind};
+    use crate::config::Route;
+    use crate::reverse_proxy_filter;
+    use std::time::Duration;
+    use warp::http::{HeaderName, StatusCode};
+    use warp::Filter;
+
+    async fn kind(route: Route, headers: &[(&str, &str)]) -> (ErrorKind, StatusCode) {
+        let mut request = warp::test::request();
+        for (name, value) in headers {
+            request = request.header(*name, *value);
+        }
+        let response = request.reply(&route.filter()).await;
+        let kind = response.extensions().get::<ErrorKind>().copied();
+        (kind.unwrap(), response.status())
+    }
+
+    #[tokio::test]
+    async fn errors_are_classified() {
+        let rejection = warp::test::request()
+            .filter(&reverse_proxy_filter(
+                "".to_string(),
+                "http://nonexistent.invalid".to_string(),
+            ))
+            .await
+            .unwrap_err();
+        let error = rejection.find::<Error>().unwrap();
+        assert_eq!(error.kind(), ErrorKind::Dns);
+        assert_eq!(
+            error.upstream().map(|url| url.to_string()),
+            Some("http://nonexistent.invalid/".to_string())
+        );
+        assert_eq!(
+            kind(Route::new("", "http://nonexistent.invalid"), &[]).await,
+            (ErrorKind::Dns, StatusCode::BAD_GATEWAY)
+        );
+        assert_eq!(
+            kind(Route::new("", "http://127.0.0.1:1"), &[]).await,
+            (ErrorKind::Connect, StatusCode::BAD_GATEWAY)
+        );
+        assert_eq!(
+            kind(
+                Route::new("", "http://127.0.0.1:1"),
+                &[("transfer-encoding", "chunked"), ("content-length", "1")]
+            )
+            .await,
+            (ErrorKind::Refused, StatusCode::BAD_REQUEST)
+        );
+
+        let (address, server) = warp::serve(warp::any().and_then(|| async {
+            tokio::time::sleep(Duration::from_millis(200)).await;
+            Ok::<_, warp::Rejection>("late")
+        }))
+        .bind_ephemeral(([127, 0, 0, 1], 0));
+        tokio::spawn(server);
+        let route = Route::new("", format!("http://{}", address))
+            .timeout_header(HeaderName::from_static("x-timeout"), Duration::from_secs(1));
+        assert_eq!(
+            kind(route, &[("x-timeout", "10")]).await,
+            (ErrorKind::Timeout, StatusCode::GATEWAY_TIMEOUT)
+        );
+    }
+}
@@ -0,0 +1,184 @@
+//! A/B experiments splitting a route traffic between upstreams.
+//!
+//! Requests are assigned a bucket by hashing a stable key taken from a header or a cookie, so
+//! a given user always lands on the same upstream while the experiment is unchanged.
+use crate::headers;
+use warp::http::{HeaderMap, HeaderName};
+
+/// Header the assigned bucket name is replied in, unless the experiment names another one
+pub static BUCKET_HEADER: HeaderName = HeaderName::from_static("x-experiment-bucket");
+
+/// Where the bucketing key of a request is read from.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ExperimentKey {
+    Header(HeaderName),
+    Cookie(String),
+}
+
+/// Weighted buckets, each served by its own upstream.
+///
+/// Requests without a key aren't part of the experiment and go to the route upstream.
+/// # Examples
+/// ```
+/// use warp_reverse_proxy::config::Route;
+/// use warp_reverse_proxy::experiment::{Experiment, ExperimentKey};
+///
+/// let route = Route::new("shop", "http://control.local").experiment(
+///     Experiment::new("checkout", ExperimentKey::Cookie("session".to_string()))
+///         .bucket("control", 90, "http://control.local")
+///         .bucket("new-checkout", 10, "http://canary.local"),
+/// );
+/// ```
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Experiment {
+    name: String,
+    key: ExperimentKey,
+    buckets: Vec<Bucket>,
+    header: Option<HeaderName>,
+}
+
+/// Bucket of an [`Experiment`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Bucket {
+    pub name: String,
+    pub weight: u32,
+    pub upstream: String,
+}
+
+impl Experiment {
+    /// Experiment without buckets, `name` salting the key hash so that different experiments
+    /// split users independently.
+    pub fn new(name: impl Into<String>, key: ExperimentKey) -> Self {
+        Self {
+            name: name.into(),
+            key,
+            buckets: Vec::new(),
+            header: None,
+        }
+    }
+
+    /// Adds a bucket taking `weight` parts of the traffic to `upstream`.
+    pub fn bucket(
+        mut self,
+        name: impl Into<String>,
+        weight: u32,
+        upstream: impl Into<String>,
+    ) -> Self {
+        self.buckets.push(Bucket {
+            name: name.into(),
+            weight,
+            upstream: upstream.into(),
+        });
+        self
+    }
+
+    /// Response header the bucket name is replied in, `X-Experiment-Bucket` by default.
+    pub fn header(mut self, header: HeaderName) -> Self {
+        self.header = Some(header);
+        self
+    }
+
+    pub fn buckets(&self) -> &[Bucket] {
+        &self.buckets
+    }
+
+    pub(crate) fn response_header(&self) -> &HeaderName {
+        self.header.as_ref().unwrap_or(&BUCKET_HEADER)
+    }
+
+    /// Bucket of a request, if it carries a key and the experiment has any weight.
+    pub fn assign(&self, headers: &HeaderMap) -> Option<&Bucket> {
+        let key = self.key(headers)?;
+        let total: u64 = self.buckets.iter().map(|b| u64::from(b.weight)).sum();
+        if total == 0 {
+            return None;
+        }
+        let mut point = fnv1a(&[self.name.as_bytes(), b"\0", key.as_bytes()]) % total;
+        self.buckets.iter().find(|bucket| {
+            let weight = u64::from(bucket.weight);
+            if point < weight {
+                true
+            } else {
+                point -= weight;
+                false
+            }
+        })
+    }
+
+    fn key<'a>(&self, headers: &'a HeaderMap) -> Option<&'a str> {
+        match &self.key {
+            ExperimentKey::Header(name) => headers.get(name)?.to_str().ok(),
+            ExperimentKey::Cookie(name) => headers::cookie(headers, name),
+        }
+        .filter(|key| !key.is_empty())
+    }
+}
+
+/// 64-bit FNV-1a, stable across platforms and releases unlike the std hasher.
+fn fnv1a(parts: &[&[u8]]) -> u64 {
+    parts
+        .iter()
+        .flat_map(|part| part.iter())
+        .fold(0xcbf2_9ce4_8422_2325, |hash, byte| {
+            (hash ^ u64::from(*byte)).wrapping_mul(0x0100_0000_01b3)
+        })
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Experiment, ExperimentKey};
+    use crate::config::Route;
+    use warp::http::{HeaderMap, HeaderName, HeaderValue};
+    use warp::Filter;
+
+    #[test]
+    fn buckets_are_sticky_and_weighted() {
+        let experiment = Experiment::new("foo", ExperimentKey::Cookie("user".to_string()))
+            .bucket("a", 1, "http://a")
+            .bucket("b", 3, "http://b");
+        let assign = |cookie: &str| {
+            let mut headers = HeaderMap::new();
+            headers.insert("cookie", HeaderValue::from_str(cookie).unwrap());
+            experiment
+                .assign(&headers)
+                .map(|bucket| bucket.name.clone())
+        };
+
+        assert_eq!(assign("theme=dark; user=42"), assign("user=42"));
+        assert_eq!(assign("user="), None);
+        assert_eq!(assign("other=42"), None);
+        let b = (0..1000)
+            .filter(|user| assign(&format!("user={}", user)).unwrap() == "b")
+            .count();
+        assert!((650..850).contains(&b), "{} users in b", b);
+    }
+
+    #[tokio::test]
+    async fn experiment_routes_buckets() {
+        let (a, a_server) =
+            warp::serve(warp::any().map(|| "a")).bind_ephemeral(([127, 0, 0, 1], 0));
+        let (control, control_server) =
+            warp::serve(warp::any().map(|| "control")).bind_ephemeral(([127, 0, 0, 1], 0));
+        tokio::spawn(a_server);
+        tokio::spawn(control_server);
+
+        let user = HeaderName::from_static("x-user");
+        let filter = Route::new("", format!("http://{}", control))
+            .experiment(Experiment::new("foo", ExperimentKey::Header(user)).bucket(
+                "a",
+                1,
+                format!("http://{}", a),
+            ))
+            .filter();
+
+        let response = warp::test::request()
+            .header("x-user", "42")
+            .reply(&filter)
+            .await;
+        assert_eq!(response.body(), "a");
+        assert_eq!(response.headers()["x-experiment-bucket"], "a");
+        let response = warp::test::request().reply(&filter).await;
+        assert_eq!(response.body(), "control");
+        assert!(!response.headers().contains_key("x-experiment-bucket"));
+    }
+}
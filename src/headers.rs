@@ -0,0 +1,235 @@
+//! Header helpers for values built by the proxy itself, and for the ones it drops.
+use crate::errors;
+use unicase::Ascii;
+use warp::http::{header, HeaderMap, HeaderValue};
+
+/// Headers removed from both forwarded requests and replied responses, as they only concern
+/// a single connection.
+///
+/// The default policy holds the RFC 7230 hop-by-hop headers and can be extended or narrowed:
+/// # Examples
+/// ```
+/// use warp_reverse_proxy::headers::HopHeaderPolicy;
+///
+/// let policy = HopHeaderPolicy::default().with("Proxy-Connection");
+/// assert!(policy.is_hop_header("proxy-connection"));
+/// ```
+/// Names are compared ignoring case.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct HopHeaderPolicy {
+    headers: Vec<Ascii<String>>,
+}
+
+impl Default for HopHeaderPolicy {
+    fn default() -> Self {
+        [
+            "Connection",
+            "Keep-Alive",
+            "Proxy-Authenticate",
+            "Proxy-Authorization",
+            "Te",
+            "Trailers",
+            "Transfer-Encoding",
+            "Upgrade",
+        ]
+        .iter()
+        .fold(Self::none(), |policy, header| policy.with(header))
+    }
+}
+
+impl HopHeaderPolicy {
+    /// Policy forwarding every header, to be filled with [`with`](#method.with).
+    pub fn none() -> Self {
+        Self {
+            headers: Vec::new(),
+        }
+    }
+
+    /// Also treats `header` as hop-by-hop.
+    pub fn with(mut self, header: &str) -> Self {
+        if !self.is_hop_header(header) {
+            self.headers.push(Ascii::new(header.to_string()));
+        }
+        self
+    }
+
+    /// Stops treating `header` as hop-by-hop, forwarding it.
+    pub fn without(mut self, header: &str) -> Self {
+        self.headers.retain(|h| h != &header);
+        self
+    }
+
+    /// Checker method to filter hop headers
+    pub fn is_hop_header(&self, header_name: &str) -> bool {
+        self.headers.iter().any(|h| h == &header_name)
+    }
+
+    /// Copy of `headers` without the hop-by-hop ones.
+    pub fn remove(&self, headers: &HeaderMap) -> HeaderMap {
+        headers
+            .iter()
+            .filter(|(k, _)| !self.is_hop_header(k.as_str()))
+            .map(|(k, v)| (k.clone(), v.clone()))
+            .collect()
+    }
+}
+
+/// Builds a header value, refusing anything but visible ASCII, spaces and tabs.
+///
+/// Values the proxy composes from request data or configuration must go through here, so a
+/// stray CR/LF can never split them into injected headers and invalid bytes fail cleanly
+/// instead of producing a malformed request.
+pub fn checked_value(value: &str) -> Result<HeaderValue, errors::Error> {
+    let visible = |b: u8| b == b'\t' || (b' '..=b'~').contains(&b);
+    match value.bytes().all(visible) {
+        true => HeaderValue::from_str(value.trim()).ok(),
+        false => None,
+    }
+    .ok_or_else(|| errors::Error::InvalidHeaderValue(value.escape_debug().to_string()))
+}
+
+/// Value of the `name` cookie among the request `Cookie` headers.
+pub fn cookie<'a>(headers: &'a HeaderMap, name: &str) -> Option<&'a str> {
+    headers
+        .get_all(header::COOKIE)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(';'))
+        .filter_map(|cookie| cookie.trim().split_once('='))
+        .find(|(cookie, _)| *cookie == name)
+        .map(|(_, value)| value)
+}
+
+/// How headers repeated in a request or response are forwarded.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum DuplicateHeaders {
+    /// Forwards every value as its own header line, the default
+    #[default]
+    Preserve,
+    /// Joins the values of a repeated header into one comma separated line, for peers that
+    /// only read the first one. `Set-Cookie` is always preserved, as joined cookies break.
+    Join,
+}
+
+impl DuplicateHeaders {
+    /// Applies the policy to a header map.
+    pub fn apply(self, headers: &mut HeaderMap) {
+        if self == DuplicateHeaders::Preserve {
+            return;
+        }
+        let repeated: Vec<_> = headers
+            .keys()
+            .filter(|name| {
+                *name != header::SET_COOKIE && headers.get_all(*name).iter().nth(1).is_some()
+            })
+            .cloned()
+            .collect();
+        for name in repeated {
+            let joined = headers
+                .get_all(&name)
+                .iter()
+                .map(HeaderValue::as_bytes)
+                .collect::<Vec<_>>()
+                .join(&b", "[..]);
+            if let Ok(value) = HeaderValue::from_bytes(&joined) {
+                headers.insert(name, value);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{checked_value, DuplicateHeaders, HopHeaderPolicy};
+    use crate::config::Route;
+    use warp::http::{HeaderMap, HeaderValue};
+    use warp::Filter;
+
+    #[test]
+    fn injected_values_refused() {
+        assert_eq!(checked_value(" for=1.2.3.4 ").unwrap(), "for=1.2.3.4");
+        assert!(checked_value("foo\r\nX-Injected: bar").is_err());
+        assert!(checked_value("foo\nbar").is_err());
+        assert!(checked_value("foo\0").is_err());
+        assert!(checked_value("caf\u{e9}").is_err());
+    }
+
+    #[test]
+    fn hop_header_policy() {
+        let mut headers = HeaderMap::new();
+        for name in ["connection", "proxy-connection", "te", "foo"] {
+            headers.insert(name, HeaderValue::from_static("bar"));
+        }
+        let keys = |policy: HopHeaderPolicy| {
+            let mut keys: Vec<_> = policy
+                .remove(&headers)
+                .keys()
+                .map(|k| k.to_string())
+                .collect();
+            keys.sort();
+            keys
+        };
+
+        assert_eq!(
+            keys(HopHeaderPolicy::default()),
+            ["foo", "proxy-connection"]
+        );
+        assert_eq!(
+            keys(
+                HopHeaderPolicy::default()
+                    .with("Proxy-Connection")
+                    .without("TE")
+            ),
+            ["foo", "te"]
+        );
+        assert_eq!(keys(HopHeaderPolicy::none()).len(), 4);
+    }
+
+    #[tokio::test]
+    async fn duplicate_headers_forwarded() {
+        let (address, server) =
+            warp::serve(warp::header::headers_cloned().map(|headers: HeaderMap| {
+                let accept: Vec<_> = headers.get_all("accept").iter().collect();
+                let mut response = warp::reply::Response::new(format!("{:?}", accept).into());
+                for cookie in ["a=1", "b=2"] {
+                    response
+                        .headers_mut()
+                        .append("set-cookie", HeaderValue::from_static(cookie));
+                    response
+                        .headers_mut()
+                        .append("vary", HeaderValue::from_static("foo"));
+                }
+                response
+            }))
+            .bind_ephemeral(([127, 0, 0, 1], 0));
+        tokio::spawn(server);
+
+        // warp test requests hold a single value per header, so go through a proxy server
+        let reply = |policy: DuplicateHeaders| async move {
+            let route = Route::new("", format!("http://{}", address)).duplicate_headers(policy);
+            let (proxy, proxy_server) =
+                warp::serve(route.filter()).bind_ephemeral(([127, 0, 0, 1], 0));
+            tokio::spawn(proxy_server);
+            let response = reqwest::Client::new()
+                .get(format!("http://{}", proxy))
+                .header("accept", "text/html")
+                .header("accept", "text/plain")
+                .send()
+                .await
+                .unwrap();
+            let headers = response.headers().clone();
+            (headers, response.text().await.unwrap())
+        };
+
+        let (headers, body) = reply(DuplicateHeaders::Preserve).await;
+        assert_eq!(body, r#"["text/html", "text/plain"]"#);
+        assert_eq!(headers.get_all("set-cookie").iter().count(), 2);
+        assert_eq!(headers.get_all("vary").iter().count(), 2);
+
+        let (headers, body) = reply(DuplicateHeaders::Join).await;
+        assert_eq!(body, r#"["text/html, text/plain"]"#);
+        assert_eq!(headers.get_all("set-cookie").iter().count(), 2);
+        let vary: Vec<_> = headers.get_all("vary").iter().collect();
+        assert_eq!(vary, ["foo, foo"]);
+    }
+}
@@ -0,0 +1,366 @@
+//! `Idempotency-Key` aware deduplication of forwarded requests.
+//!
+//! When a route has an [`IdempotencyStore`], requests carrying an `Idempotency-Key` header are
+//! looked up in it first: a stored response is replayed without reaching the upstream, while
+//! the upstream response to a first-seen key is buffered and stored.
+//!
+//! Keys are scoped by method, path, query and a hash of the `Authorization` and `Cookie`
+//! headers, so the same key sent to different endpoints or by different clients doesn't
+//! collide. Requests without credentials share a scope. Reusing a key with a different body
+//! is refused with [`errors::Error::IdempotencyKeyReused`], replied
+//! `422 Unprocessable Entity`. Streamed request bodies aren't read by the proxy, so they aren't
+//! compared.
+//!
+//! Upstream server errors (5xx) are not stored, so the client can retry them, nor are responses
+//! larger than the store [`max_body_bytes`](trait.IdempotencyStore.html#method.max_body_bytes),
+//! which are streamed instead. Duplicates of those, and duplicates sent while the first request
+//! is still in flight, are forwarded too.
+use crate::errors;
+use crate::headers::HopHeaderPolicy;
+use futures_util::{future, stream, StreamExt};
+use sha1::{Digest as _, Sha1};
+use std::collections::{HashMap, VecDeque};
+use std::sync::Mutex;
+use warp::http::{self, header, HeaderMap, HeaderName, Method, StatusCode};
+use warp::hyper::body::Bytes;
+use warp::hyper::Body;
+
+pub(crate) static IDEMPOTENCY_KEY: HeaderName = HeaderName::from_static("idempotency-key");
+
+/// Default [`IdempotencyStore::max_body_bytes`], 1MiB.
+pub const DEFAULT_MAX_BODY_BYTES: u64 = 1024 * 1024;
+
+/// Buffered upstream response kept for replays.
+#[derive(Clone, Debug)]
+pub struct StoredResponse {
+    /// Hash of the body of the request the response answers
+    pub request_hash: String,
+    pub status: StatusCode,
+    pub headers: HeaderMap,
+    pub body: Bytes,
+}
+
+/// Storage of the responses to already seen idempotency keys.
+pub trait IdempotencyStore: Send + Sync {
+    fn get(&self, key: &str) -> Option<StoredResponse>;
+
+    fn put(&self, key: &str, response: StoredResponse);
+
+    /// Largest response body stored, [`DEFAULT_MAX_BODY_BYTES`] unless overridden.
+    fn max_body_bytes(&self) -> u64 {
+        DEFAULT_MAX_BODY_BYTES
+    }
+}
+
+impl std::fmt::Debug for dyn IdempotencyStore {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("IdempotencyStore")
+    }
+}
+
+/// In-memory store keeping the latest `capacity` responses.
+#[derive(Debug)]
+pub struct MemoryStore {
+    capacity: usize,
+    max_body_bytes: u64,
+    entries: Mutex<(HashMap<String, StoredResponse>, VecDeque<String>)>,
+}
+
+impl MemoryStore {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            max_body_bytes: DEFAULT_MAX_BODY_BYTES,
+            entries: Mutex::new((HashMap::new(), VecDeque::new())),
+        }
+    }
+
+    /// Largest response body stored, bounding the store memory to about
+    /// `capacity * max_body_bytes`.
+    pub fn max_body_bytes(mut self, bytes: u64) -> Self {
+        self.max_body_bytes = bytes;
+        self
+    }
+}
+
+impl IdempotencyStore for MemoryStore {
+    fn get(&self, key: &str) -> Option<StoredResponse> {
+        self.entries.lock().unwrap().0.get(key).cloned()
+    }
+
+    fn put(&self, key: &str, response: StoredResponse) {
+        let (entries, order) = &mut *self.entries.lock().unwrap();
+        if entries.insert(key.to_string(), response).is_none() {
+            order.push_back(key.to_string());
+        }
+        while order.len() > self.capacity {
+            if let Some(oldest) = order.pop_front() {
+                entries.remove(&oldest);
+            }
+        }
+    }
+
+    fn max_body_bytes(&self) -> u64 {
+        self.max_body_bytes
+    }
+}
+
+impl StoredResponse {
+    fn to_reply(&self) -> Result<http::Response<Body>, errors::Error> {
+        reply(self.status, &self.headers, Body::from(self.body.clone()))
+    }
+}
+
+fn reply(
+    status: StatusCode,
+    headers: &HeaderMap,
+    body: Body,
+) -> Result<http::Response<Body>, errors::Error> {
+    let mut builder = http::Response::builder().status(status);
+    for (k, v) in headers.iter() {
+        builder = builder.header(k, v);
+    }
+    builder.body(body).map_err(errors::Error::Http)
+}
+
+/// Store key of a request carrying an idempotency key, with the hash of its body.
+#[derive(Clone, Debug)]
+pub(crate) struct Key {
+    key: String,
+    request_hash: String,
+}
+
+/// Store key of a request, if it carries an idempotency key.
+pub(crate) fn key(
+    method: &Method,
+    path: &str,
+    query: Option<&str>,
+    headers: &HeaderMap,
+    body: &[u8],
+) -> Option<Key> {
+    let key = headers.get(&IDEMPOTENCY_KEY)?.to_str().ok()?;
+    let mut credentials = Sha1::new();
+    for name in [header::AUTHORIZATION, header::COOKIE] {
+        for value in headers.get_all(&name) {
+            credentials.update(name.as_str());
+            credentials.update([0]);
+            credentials.update(value.as_bytes());
+            credentials.update([0]);
+        }
+    }
+    Some(Key {
+        key: format!(
+            "{} {}?{} {} {}",
+            method,
+            path,
+            query.unwrap_or_default(),
+            hex(&credentials.finalize()),
+            key
+        ),
+        request_hash: hex(&Sha1::digest(body)),
+    })
+}
+
+/// Reply stored for `key`, if any, refusing requests reusing it with a different body.
+pub(crate) fn replay(
+    store: &dyn IdempotencyStore,
+    key: &Key,
+) -> Option<Result<http::Response<Body>, errors::Error>> {
+    store
+        .get(&key.key)
+        .map(|stored| match stored.request_hash == key.request_hash {
+            true => stored.to_reply(),
+            false => Err(errors::Error::IdempotencyKeyReused),
+        })
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+/// Buffers and stores an upstream response before replying it, or streams it without storing
+/// it once it is larger than the store [`max_body_bytes`](IdempotencyStore::max_body_bytes).
+pub(crate) async fn store(
+    store: &dyn IdempotencyStore,
+    key: &Key,
+    hop_headers: &HopHeaderPolicy,
+    mut response: reqwest::Response,
+) -> Result<http::Response<Body>, errors::Error> {
+    let status = response.status();
+    let headers = hop_headers.remove(response.headers());
+    let max = store.max_body_bytes();
+    if response.content_length().is_some_and(|length| length > max) {
+        return reply(status, &headers, Body::wrap_stream(response.bytes_stream()));
+    }
+    let mut body = Vec::new();
+    while let Some(chunk) = response.chunk().await? {
+        body.extend_from_slice(&chunk);
+        if body.len() as u64 > max {
+            let read = stream::once(future::ready(Ok(Bytes::from(body))));
+            let body = Body::wrap_stream(read.chain(response.bytes_stream()));
+            return reply(status, &headers, body);
+        }
+    }
+    let stored = StoredResponse {
+        request_hash: key.request_hash.clone(),
+        status,
+        headers,
+        body: body.into(),
+    };
+    if !status.is_server_error() {
+        store.put(&key.key, stored.clone());
+    }
+    stored.to_reply()
+}
+
+#[cfg(test)]
+mod test {
+    use super::{IdempotencyStore, MemoryStore, StoredResponse};
+    use crate::config::Route;
+    use crate::context::ProxyInfo;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use warp::http::{HeaderMap, StatusCode};
+    use warp::Filter;
+
+    #[test]
+    fn memory_store_evicts_oldest() {
+        let store = MemoryStore::new(1);
+        let response = StoredResponse {
+            request_hash: String::new(),
+            status: StatusCode::OK,
+            headers: HeaderMap::new(),
+            body: "foo".into(),
+        };
+        store.put("foo", response.clone());
+        store.put("bar", response);
+        assert!(store.get("foo").is_none());
+        assert_eq!(store.get("bar").unwrap().body, "foo");
+    }
+
+    #[tokio::test]
+    async fn duplicates_are_replayed() {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let counter = calls.clone();
+        let (address, server) = warp::serve(
+            warp::any().map(move || counter.fetch_add(1, Ordering::SeqCst).to_string()),
+        )
+        .bind_ephemeral(([127, 0, 0, 1], 0));
+        tokio::spawn(server);
+
+        let filter = Route::new("", format!("http://{}", address))
+            .idempotency(Arc::new(MemoryStore::new(8)))
+            .filter();
+        let request = |path: &'static str, key: Option<&'static str>| {
+            let filter = filter.clone();
+            async move {
+                let mut request = warp::test::request().method("POST").path(path);
+                if let Some(key) = key {
+                    request = request.header("idempotency-key", key);
+                }
+                request.reply(&filter).await.into_body()
+            }
+        };
+
+        assert_eq!(request("/pay", Some("foo")).await, "0");
+        assert_eq!(request("/pay", Some("foo")).await, "0");
+        assert_eq!(request("/pay", Some("bar")).await, "1");
+        assert_eq!(request("/refund", Some("foo")).await, "2");
+        assert_eq!(request("/pay", None).await, "3");
+        assert_eq!(calls.load(Ordering::SeqCst), 4);
+
+        let replayed = |response: warp::http::Response<warp::hyper::Body>| {
+            response.extensions().get::<ProxyInfo>().unwrap().replayed
+        };
+        let request = || {
+            warp::test::request()
+                .method("POST")
+                .path("/pay")
+                .header("idempotency-key", "baz")
+        };
+        assert!(!replayed(request().filter(&filter).await.unwrap()));
+        assert!(replayed(request().filter(&filter).await.unwrap()));
+    }
+
+    #[tokio::test]
+    async fn keys_are_scoped_and_bound_to_bodies() {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let counter = calls.clone();
+        let (address, server) = warp::serve(
+            warp::any().map(move || counter.fetch_add(1, Ordering::SeqCst).to_string()),
+        )
+        .bind_ephemeral(([127, 0, 0, 1], 0));
+        tokio::spawn(server);
+
+        let filter = Route::new("", format!("http://{}", address))
+            .idempotency(Arc::new(MemoryStore::new(8)))
+            .filter()
+            .recover(crate::errors::recover);
+        let request = |path: &'static str, credentials: &'static [(&'static str, &'static str)]| {
+            let filter = filter.clone();
+            move |body: &'static str| {
+                let filter = filter.clone();
+                async move {
+                    let mut request = warp::test::request()
+                        .method("POST")
+                        .path(path)
+                        .header("idempotency-key", "foo")
+                        .body(body);
+                    for (name, value) in credentials {
+                        request = request.header(*name, *value);
+                    }
+                    let response = request.reply(&filter).await;
+                    (response.status(), response.into_body())
+                }
+            }
+        };
+        let alice = request("/pay", &[("authorization", "Bearer alice")]);
+        let bob = request("/pay", &[("authorization", "Bearer bob")]);
+        let cookie = request("/pay", &[("cookie", "session=carol")]);
+        let query = request("/pay?amount=2", &[("authorization", "Bearer alice")]);
+
+        assert_eq!(alice("10").await, (StatusCode::OK, "0".into()));
+        assert_eq!(alice("10").await, (StatusCode::OK, "0".into()));
+        assert_eq!(bob("10").await, (StatusCode::OK, "1".into()));
+        assert_eq!(cookie("10").await, (StatusCode::OK, "2".into()));
+        assert_eq!(query("10").await, (StatusCode::OK, "3".into()));
+        let (status, _) = alice("20").await;
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+        assert_eq!(calls.load(Ordering::SeqCst), 4);
+    }
+
+    #[tokio::test]
+    async fn large_responses_are_streamed_not_stored() {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let (declared, chunked) = (calls.clone(), calls.clone());
+        let (address, server) = warp::serve(
+            warp::path!("declared")
+                .map(move || format!("{}-large", declared.fetch_add(1, Ordering::SeqCst)))
+                .or(warp::path!("chunked").map(move || {
+                    let call = chunked.fetch_add(1, Ordering::SeqCst).to_string();
+                    let chunks = vec![Ok::<_, std::io::Error>(call), Ok("-large".to_string())];
+                    warp::http::Response::new(warp::hyper::Body::wrap_stream(
+                        futures_util::stream::iter(chunks),
+                    ))
+                })),
+        )
+        .bind_ephemeral(([127, 0, 0, 1], 0));
+        tokio::spawn(server);
+
+        let filter = Route::new("", format!("http://{}", address))
+            .idempotency(Arc::new(MemoryStore::new(8).max_body_bytes(4)))
+            .filter();
+        let request = |path: &'static str| {
+            warp::test::request()
+                .method("POST")
+                .path(path)
+                .header("idempotency-key", "foo")
+                .reply(&filter)
+        };
+
+        assert_eq!(request("/declared").await.into_body(), "0-large");
+        assert_eq!(request("/declared").await.into_body(), "1-large");
+        assert_eq!(request("/chunked").await.into_body(), "2-large");
+        assert_eq!(request("/chunked").await.into_body(), "3-large");
+    }
+}
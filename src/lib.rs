@@ -28,14 +28,47 @@
 //!     warp::serve(app).run(([0, 0, 0, 0], 3030)).await;
 //! }
 //! ```
+//!
+//! Upstream interim responses, `1xx` other than `101 Switching Protocols`, such as the
+//! `100 Continue` or `102 Processing` of WebDAV servers, are skipped by the client waiting for
+//! the final response, which is replied as usual. They can't be forwarded: warp handlers
+//! reply a single response per request.
+pub mod audit;
+#[cfg(feature = "auth")]
+pub mod auth;
+pub mod config;
+pub mod context;
+pub mod contract;
+pub mod csrf;
+#[cfg(feature = "digest")]
+mod digest;
+mod dns;
 pub mod errors;
+pub mod experiment;
+pub mod headers;
+#[cfg(feature = "idempotency")]
+pub mod idempotency;
+pub mod limits;
+pub mod media;
+pub mod redact;
+#[cfg(all(feature = "unix", unix))]
+pub mod reload;
+pub mod schedule;
+pub mod shutdown;
+#[cfg(feature = "digest")]
+pub mod signed_url;
+pub mod streaming;
+pub mod template;
+pub mod testing;
+pub mod traffic;
+#[cfg(feature = "websocket")]
+pub mod websocket;
 
-use once_cell::sync::{Lazy, OnceCell};
+use once_cell::sync::OnceCell;
 use reqwest::redirect::Policy;
-use unicase::Ascii;
 use warp::filters::path::FullPath;
 use warp::http;
-use warp::http::{HeaderMap, HeaderValue, Method as RequestMethod};
+use warp::http::{HeaderMap, Method as RequestMethod};
 use warp::hyper::body::Bytes;
 use warp::hyper::Body;
 use warp::{Filter, Rejection};
@@ -72,6 +105,24 @@ pub type Headers = HeaderMap;
 /// It is the type that holds the request data extracted by the [`extract_request_data_filter`](fn.extract_request_data_filter.html) filter.
 pub type Request = (Uri, QueryParameters, Method, Headers, Bytes);
 
+/// Request extension overriding the upstream address a request is forwarded to.
+///
+/// When inserted into the request extensions before the proxy filters run (by a tower layer or
+/// a hyper service wrapping warp), it replaces the `proxy_address` of
+/// [`reverse_proxy_filter`](fn.reverse_proxy_filter.html) and the upstream of the matched
+/// [`config::Route`](config/struct.Route.html), pinning the request to a specific backend.
+/// The `base_path` is still stripped from the request path.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ForcedUpstream(pub String);
+
+/// Upstream address of a request, `default` unless a [`ForcedUpstream`] overrides it.
+fn upstream_filter(
+    default: String,
+) -> impl Filter<Extract = (String,), Error = std::convert::Infallible> + Clone {
+    warp::ext::optional::<ForcedUpstream>()
+        .map(move |forced: Option<ForcedUpstream>| forced.map_or_else(|| default.clone(), |f| f.0))
+}
+
 /// Reverse proxy filter
 ///
 /// Forwards the request to the desired location. It maps one to one, meaning
@@ -81,7 +132,7 @@ pub type Request = (Uri, QueryParameters, Method, Headers, Bytes);
 /// # Arguments
 ///
 /// * `base_path` - A string with the initial relative path of the endpoint.
-/// For example a `foo/` applied for an endpoint `foo/bar/` will result on a proxy to `bar/` (hence `/foo` is removed)
+///   For example a `foo/` applied for an endpoint `foo/bar/` will result on a proxy to `bar/` (hence `/foo` is removed)
 ///
 /// * `proxy_address` - Base proxy address to forward request.
 /// # Examples
@@ -93,14 +144,28 @@ pub fn reverse_proxy_filter(
     base_path: String,
     proxy_address: String,
 ) -> impl Filter<Extract = (http::Response<Body>,), Error = Rejection> + Clone {
-    let proxy_address = warp::any().map(move || proxy_address.clone());
+    let proxy_address = upstream_filter(proxy_address);
     let base_path = warp::any().map(move || base_path.clone());
     let data_filter = extract_request_data_filter();
 
     proxy_address
         .and(base_path)
+        .and(context::filter())
         .and(data_filter)
-        .and_then(proxy_to_and_forward_response)
+        .and_then(
+            |proxy_address, base_path, context, uri, params, method, headers, body| async move {
+                forward(
+                    default_client(),
+                    &ForwardOptions::default(),
+                    context,
+                    proxy_address,
+                    base_path,
+                    (uri, params, method, headers, body),
+                )
+                .await
+                .map_err(warp::reject::custom)
+            },
+        )
         .boxed()
 }
 
@@ -129,7 +194,7 @@ pub fn extract_request_data_filter(
 /// # Arguments
 ///
 /// * `proxy_address` - A string containing the base proxy address where the request
-/// will be forwarded to.
+///   will be forwarded to.
 ///
 /// * `base_path` - A string with the prepended sub-path to be stripped from the request uri path.
 ///
@@ -174,21 +239,508 @@ pub async fn proxy_to_and_forward_response(
     headers: HeaderMap,
     body: Bytes,
 ) -> Result<http::Response<Body>, Rejection> {
-    let proxy_uri = remove_relative_path(&uri, base_path, proxy_address);
-    let request = filtered_data_to_request(proxy_uri, (uri, params, method, headers, body))
-        .map_err(warp::reject::custom)?;
-    let response = proxy_request(request).await.map_err(warp::reject::custom)?;
-    response_to_reply(response)
-        .await
-        .map_err(warp::reject::custom)
+    forward(
+        default_client(),
+        &ForwardOptions::default(),
+        context::RequestContext::default(),
+        proxy_address,
+        base_path,
+        (uri, params, method, headers, body),
+    )
+    .await
+    .map_err(warp::reject::custom)
+}
+
+/// Build a request and send it to the requested address, returning the raw upstream response.
+///
+/// Lower level building block of [`proxy_to_and_forward_response`](fn.proxy_to_and_forward_response.html)
+/// for handlers that need to inspect or stream the upstream response before deciding how to reply.
+/// The request goes through the same framing checks and hop-by-hop header removal, but the
+/// response is returned untouched, hop-by-hop headers included.
+///
+/// # Examples
+/// ```rust, no_run
+/// use warp::Filter;
+/// use warp_reverse_proxy::{extract_request_data_filter, proxy_to};
+///
+/// let app = warp::path!("hello" / ..)
+///     .and(extract_request_data_filter())
+///     .and_then(|uri, params, method, headers, body| async move {
+///         let response = proxy_to(
+///             "http://127.0.0.1:8080".to_string(),
+///             "hello".to_string(),
+///             (uri, params, method, headers, body),
+///         )
+///         .await
+///         .map_err(warp::reject::custom)?;
+///         Ok::<_, warp::Rejection>(response.status().to_string())
+///     });
+/// ```
+pub async fn proxy_to(
+    proxy_address: String,
+    base_path: String,
+    request: Request,
+) -> Result<reqwest::Response, errors::Error> {
+    let (method, path) = (&request.2, request.0.as_str());
+    check_framing(&request.3).map_err(|e| audit::denied(method, path, "framing", e))?;
+    let proxy_uri = remove_relative_path(&request.0, base_path, proxy_address);
+    let request = filtered_data_to_request(
+        default_client(),
+        &headers::HopHeaderPolicy::default(),
+        proxy_uri,
+        request,
+    )?;
+    proxy_request(default_client(), request).await
+}
+
+/// Forwarding settings beyond the plain filters behavior, which the defaults keep.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct ForwardOptions {
+    /// Headers dropped when forwarding requests and replying responses
+    pub(crate) hop_headers: headers::HopHeaderPolicy,
+    /// Longest upstream URI, query included, that is forwarded
+    pub(crate) max_uri_length: Option<usize>,
+    /// Reject request bodies not matching their `Digest` header
+    #[cfg(feature = "digest")]
+    pub(crate) verify_digest: bool,
+    /// Send a `Digest` header computed from the forwarded body
+    #[cfg(feature = "digest")]
+    pub(crate) digest: bool,
+    /// Read whole upstream responses before replying instead of streaming them
+    pub(crate) buffer_response: bool,
+    /// Assertions upstream responses must pass
+    pub(crate) contract: Option<contract::ResponseContract>,
+    /// Header the request [`context::Annotations`] are sent in, instead of the default one
+    pub(crate) annotations_header: Option<http::header::HeaderName>,
+    /// Header carrying a client requested timeout in milliseconds, and its upper bound
+    pub(crate) timeout_header: Option<(http::header::HeaderName, std::time::Duration)>,
+    /// Only request headers forwarded upstream, when set
+    pub(crate) allowed_headers: Option<Vec<http::header::HeaderName>>,
+    /// Only response headers replied to clients, when set
+    pub(crate) allowed_response_headers: Option<Vec<http::header::HeaderName>>,
+    /// `User-Agent` sent upstream instead of the client one
+    pub(crate) user_agent: Option<config::UserAgent>,
+    /// Identify the proxy to upstreams with an `X-Proxied-By` header
+    pub(crate) proxied_by: bool,
+    /// Template request paths must match, capturing segments
+    pub(crate) path_template: Option<template::PathTemplate>,
+    /// Upstream path built from the template captures, instead of the request one
+    pub(crate) upstream_path: Option<String>,
+    /// Headers sent upstream with values built from the template captures
+    pub(crate) capture_headers: Vec<(http::header::HeaderName, String)>,
+    /// Resolve dot segments and duplicate slashes of request paths
+    pub(crate) normalize_path: bool,
+    /// Whether repeated headers are forwarded as is or joined
+    pub(crate) duplicate_headers: headers::DuplicateHeaders,
+    /// Reply the time spent upstream and in the proxy in a `Server-Timing` header
+    pub(crate) server_timing: bool,
+    /// Byte counts the upstream exchanges are added to
+    pub(crate) traffic: Option<traffic::Traffic>,
+    /// Replay responses to already seen `Idempotency-Key`s
+    #[cfg(feature = "idempotency")]
+    pub(crate) idempotency: Option<std::sync::Arc<dyn idempotency::IdempotencyStore>>,
+}
+
+/// Upstream request a client request is forwarded as, built without being sent.
+///
+/// Extracted by [`config::Route::dry_run`](config/struct.Route.html#method.dry_run) to check
+/// routing and header policies from tests or tooling.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct OutboundRequest {
+    pub method: Method,
+    /// Upstream URI, query included
+    pub uri: String,
+    /// Headers left by the route policies. The client still adds `Host`, and `Accept` when
+    /// missing, while sending.
+    pub headers: HeaderMap,
+    /// Body length in bytes, unknown for streamed bodies
+    pub body_length: Option<usize>,
+}
+
+impl OutboundRequest {
+    /// Copy with the [`redact::REDACTION`] rules applied to the URI and headers, for logging.
+    pub fn redacted(&self) -> Self {
+        let redaction = redact::current();
+        Self {
+            method: self.method.clone(),
+            uri: redaction.uri(&self.uri),
+            headers: redaction.headers(&self.headers),
+            body_length: self.body_length,
+        }
+    }
+}
+
+impl From<&reqwest::Request> for OutboundRequest {
+    fn from(request: &reqwest::Request) -> Self {
+        Self {
+            method: request.method().clone(),
+            uri: request.url().to_string(),
+            headers: request.headers().clone(),
+            body_length: request
+                .body()
+                .and_then(reqwest::Body::as_bytes)
+                .map(<[u8]>::len)
+                .or_else(|| request.body().is_none().then_some(0)),
+        }
+    }
+}
+
+/// Idempotency store key of a request
+#[cfg(feature = "idempotency")]
+type IdempotencyKey = idempotency::Key;
+#[cfg(not(feature = "idempotency"))]
+type IdempotencyKey = std::convert::Infallible;
+
+/// Outcome of the forwarding pipeline up to the upstream call.
+pub(crate) enum Outbound {
+    /// Request to send upstream, with the idempotency key its response is stored under
+    Send(reqwest::Request, context::ProxyInfo, Option<IdempotencyKey>),
+    /// Stored reply to an already seen idempotency key
+    #[cfg(feature = "idempotency")]
+    Replay(http::Response<Body>),
+}
+
+/// Forwards the request data through `client` and converts the upstream response.
+pub(crate) async fn forward(
+    client: &reqwest::Client,
+    options: &ForwardOptions,
+    context: context::RequestContext,
+    proxy_address: String,
+    base_path: String,
+    request: Request,
+) -> Result<http::Response<Body>, errors::Error> {
+    let mut timing = Timing::start();
+    let counter = options
+        .traffic
+        .as_ref()
+        .map(|traffic| traffic.counter(&proxy_address));
+    let streamed_bytes = context.streamed_bytes.clone();
+    let outbound = prepare(client, options, context, proxy_address, base_path, request)?;
+    let (request, info, key) = match outbound {
+        Outbound::Send(request, info, key) => (request, info, key),
+        #[cfg(feature = "idempotency")]
+        Outbound::Replay(reply) => {
+            timing.cache = Some("hit");
+            return Ok(finish(options, reply, &timing));
+        }
+    };
+    if let Some(counter) = &counter {
+        let length = request
+            .body()
+            .and_then(reqwest::Body::as_bytes)
+            .map_or(0, <[u8]>::len);
+        counter.add_request(length as u64);
+    }
+    let in_flight = InFlight::new(&info.upstream);
+    let reply = send(client, options, request, &info, key, &mut timing).await;
+    in_flight.finish();
+    if let (Some(counter), Some(streamed)) = (&counter, streamed_bytes) {
+        counter.add_request(streamed.load(std::sync::atomic::Ordering::Relaxed));
+    }
+    reply.map(|reply| {
+        let reply = finish(options, reply, &timing);
+        info.attach(match counter {
+            Some(counter) => counter.count_response(reply),
+            None => reply,
+        })
+    })
+}
+
+/// Sends the upstream request and converts its response, storing it under the idempotency
+/// key if any.
+async fn send(
+    client: &reqwest::Client,
+    options: &ForwardOptions,
+    request: reqwest::Request,
+    info: &context::ProxyInfo,
+    key: Option<IdempotencyKey>,
+    timing: &mut Timing,
+) -> Result<http::Response<Body>, errors::Error> {
+    let sent = std::time::Instant::now();
+    let response = proxy_request(client, request).await?;
+    timing.upstream = Some(sent.elapsed());
+    if let Some(contract) = &options.contract {
+        contract
+            .check(response.status(), response.headers())
+            .map_err(|e| {
+                log::warn!("response from {}: {}", info.upstream, e);
+                e
+            })?;
+    }
+    #[cfg(feature = "idempotency")]
+    if let (Some(store), Some(key)) = (&options.idempotency, &key) {
+        timing.cache = Some("miss");
+        return idempotency::store(store.as_ref(), key, &options.hop_headers, response).await;
+    }
+    #[cfg(not(feature = "idempotency"))]
+    let _ = key;
+    if !options.buffer_response {
+        return response_to_reply(&options.hop_headers, response).await;
+    }
+    #[cfg(feature = "digest")]
+    if options.digest {
+        let (mut builder, body) = buffered_response(&options.hop_headers, response).await?;
+        if let Some(headers) = builder.headers_mut() {
+            headers.insert(&digest::DIGEST, digest::value(&body));
+        }
+        return builder.body(Body::from(body)).map_err(errors::Error::Http);
+    }
+    buffered_reply(&options.hop_headers, response).await
+}
+
+/// Upstream call in progress, logged if dropped before finishing.
+///
+/// hyper drops the request future when the client disconnects, which drops and cancels the
+/// upstream call instead of letting it run to completion.
+struct InFlight<'a> {
+    upstream: &'a str,
+    started: std::time::Instant,
+    finished: bool,
+}
+
+impl<'a> InFlight<'a> {
+    fn new(upstream: &'a str) -> Self {
+        Self {
+            upstream,
+            started: std::time::Instant::now(),
+            finished: false,
+        }
+    }
+
+    fn finish(mut self) {
+        self.finished = true;
+    }
+}
+
+impl Drop for InFlight<'_> {
+    fn drop(&mut self) {
+        if !self.finished {
+            log::info!(
+                target: "warp_reverse_proxy::disconnect",
+                "client disconnected after {:?}, cancelled request to {}",
+                self.started.elapsed(),
+                self.upstream
+            );
+        }
+    }
+}
+
+/// Durations of a forwarded request, replied in the `Server-Timing` header.
+struct Timing {
+    started: std::time::Instant,
+    /// From sending the upstream request to receiving the response headers
+    upstream: Option<std::time::Duration>,
+    /// Whether the reply is replayed from the idempotency store
+    cache: Option<&'static str>,
+}
+
+impl Timing {
+    fn start() -> Self {
+        Self {
+            started: std::time::Instant::now(),
+            upstream: None,
+            cache: None,
+        }
+    }
+
+    /// `upstream;dur=12.3, total;dur=13.0`, durations in milliseconds.
+    fn header_value(&self) -> String {
+        let millis = |duration: std::time::Duration| duration.as_secs_f64() * 1000.0;
+        let mut metrics = Vec::new();
+        if let Some(upstream) = self.upstream {
+            metrics.push(format!("upstream;dur={:.1}", millis(upstream)));
+        }
+        metrics.push(format!("total;dur={:.1}", millis(self.started.elapsed())));
+        if let Some(cache) = self.cache {
+            metrics.push(format!("cache;desc={}", cache));
+        }
+        metrics.join(", ")
+    }
+}
+
+/// Checks the request data and builds the upstream request, or finds a stored reply to it.
+pub(crate) fn prepare(
+    client: &reqwest::Client,
+    options: &ForwardOptions,
+    context: context::RequestContext,
+    proxy_address: String,
+    base_path: String,
+    request: Request,
+) -> Result<Outbound, errors::Error> {
+    let (method, path) = (&request.2, request.0.as_str());
+    let denied = |rule| move |e| audit::denied(method, path, rule, e);
+    check_framing(&request.3).map_err(denied("framing"))?;
+    #[cfg(feature = "digest")]
+    if (options.verify_digest || options.digest) && context.body.is_some() {
+        return Err(errors::Error::Config(
+            "digests need a buffered request body".to_string(),
+        ));
+    }
+    #[cfg(feature = "digest")]
+    if options.verify_digest {
+        digest::verify(&request.3, &request.4).map_err(denied("digest"))?;
+    }
+    let request_path = match options.normalize_path {
+        true => normalize_path(path),
+        false => path.to_string(),
+    };
+    let captures = options
+        .path_template
+        .as_ref()
+        .and_then(|template| template.captures(relative_path(&request_path, &base_path)));
+    let proxy_uri = match (&captures, &options.upstream_path) {
+        (Some(captures), Some(upstream_path)) => format!(
+            "{}/{}",
+            proxy_address.trim_end_matches('/'),
+            captures.expand(upstream_path).trim_start_matches('/')
+        ),
+        _ => upstream_path(&request_path, base_path, proxy_address),
+    };
+    check_uri_length(options, &proxy_uri, &request.1).map_err(denied("uri_length"))?;
+    let info = context::ProxyInfo::new(&proxy_uri);
+    #[cfg(feature = "idempotency")]
+    let key = match &options.idempotency {
+        Some(_) => idempotency::key(method, path, request.1.as_deref(), &request.3, &request.4),
+        None => None,
+    };
+    #[cfg(not(feature = "idempotency"))]
+    let key = None;
+    #[cfg(feature = "idempotency")]
+    if let (Some(store), Some(key)) = (&options.idempotency, &key) {
+        if let Some(reply) = idempotency::replay(store.as_ref(), key) {
+            let info = context::ProxyInfo {
+                replayed: true,
+                ..info
+            };
+            let reply = reply.map_err(denied("idempotency"))?;
+            return Ok(Outbound::Replay(info.attach(reply)));
+        }
+    }
+    let mut request = request;
+    let timeout = requested_timeout(options, &mut request.3);
+    if let Some(allowed) = &options.allowed_headers {
+        request.3 = request
+            .3
+            .iter()
+            .filter(|(name, _)| allowed.contains(name))
+            .map(|(name, value)| (name.clone(), value.clone()))
+            .collect();
+    }
+    #[cfg(feature = "digest")]
+    if options.digest {
+        request.3.insert(&digest::DIGEST, digest::value(&request.4));
+    }
+    identify(options, &mut request.3);
+    if let Some(captures) = &captures {
+        for (header, template) in &options.capture_headers {
+            request
+                .3
+                .insert(header, headers::checked_value(&captures.expand(template))?);
+        }
+    }
+    if let Some(annotations) = context.annotations.as_ref().filter(|a| !a.is_empty()) {
+        let header = options
+            .annotations_header
+            .as_ref()
+            .unwrap_or(&context::ANNOTATIONS_HEADER);
+        request.3.insert(header, annotations.header_value()?);
+    }
+    options.duplicate_headers.apply(&mut request.3);
+    let mut request = filtered_data_to_request(client, &options.hop_headers, proxy_uri, request)?;
+    if timeout.is_some() {
+        *request.timeout_mut() = timeout;
+    }
+    if let Some(body) = context.body {
+        *request.body_mut() = Some(body);
+    }
+    Ok(Outbound::Send(request, info, key))
+}
+
+/// Applies the response header policies to a reply.
+fn finish(
+    options: &ForwardOptions,
+    mut reply: http::Response<Body>,
+    timing: &Timing,
+) -> http::Response<Body> {
+    if let Some(allowed) = &options.allowed_response_headers {
+        let headers = reply.headers_mut();
+        let dropped: Vec<_> = headers
+            .keys()
+            .filter(|name| !allowed.contains(name))
+            .cloned()
+            .collect();
+        for name in dropped {
+            headers.remove(name);
+        }
+    }
+    if options.server_timing {
+        if let Ok(value) = http::HeaderValue::from_str(&timing.header_value()) {
+            reply.headers_mut().append(
+                http::header::HeaderName::from_static("server-timing"),
+                value,
+            );
+        }
+    }
+    options.duplicate_headers.apply(reply.headers_mut());
+    reply
+}
+
+/// Takes the client requested timeout out of the headers, clamped to the route maximum.
+fn requested_timeout(
+    options: &ForwardOptions,
+    headers: &mut HeaderMap,
+) -> Option<std::time::Duration> {
+    let (header, max) = options.timeout_header.as_ref()?;
+    let value = headers.remove(header)?;
+    let requested = value
+        .to_str()
+        .ok()
+        .and_then(|value| value.trim().parse().ok())
+        .map(std::time::Duration::from_millis);
+    let timeout = requested.map(|requested| requested.min(*max));
+    log::debug!(
+        "{} {:?} requested, upstream timeout {:?}",
+        header,
+        value,
+        timeout
+    );
+    timeout
+}
+
+/// Applies the route `User-Agent` and `X-Proxied-By` settings to the forwarded headers.
+fn identify(options: &ForwardOptions, headers: &mut HeaderMap) {
+    match &options.user_agent {
+        Some(config::UserAgent::Set(value)) => {
+            headers.insert(http::header::USER_AGENT, value.clone());
+        }
+        Some(config::UserAgent::Remove) => {
+            headers.remove(http::header::USER_AGENT);
+        }
+        None => {}
+    }
+    if options.proxied_by {
+        headers.insert(
+            http::header::HeaderName::from_static("x-proxied-by"),
+            http::HeaderValue::from_static(concat!(
+                env!("CARGO_PKG_NAME"),
+                "/",
+                env!("CARGO_PKG_VERSION")
+            )),
+        );
+    }
 }
 
 /// Converts a reqwest response into a http::Response
-async fn response_to_reply(
+///
+/// The body is streamed and the headers of `hop_headers` are removed, as the proxy filters
+/// reply them.
+pub async fn response_to_reply(
+    hop_headers: &headers::HopHeaderPolicy,
     response: reqwest::Response,
 ) -> Result<http::Response<Body>, errors::Error> {
     let mut builder = http::Response::builder();
-    for (k, v) in remove_hop_headers(response.headers()).iter() {
+    for (k, v) in hop_headers.remove(response.headers()).iter() {
         builder = builder.header(k, v);
     }
     let status = response.status();
@@ -199,54 +751,170 @@ async fn response_to_reply(
         .map_err(errors::Error::Http)
 }
 
-fn remove_relative_path(uri: &FullPath, base_path: String, proxy_address: String) -> String {
-    let mut base_path = base_path;
-    if !base_path.starts_with('/') {
-        base_path = format!("/{}", base_path);
+/// Converts a reqwest response into a http::Response, reading the whole body first.
+async fn buffered_reply(
+    hop_headers: &headers::HopHeaderPolicy,
+    response: reqwest::Response,
+) -> Result<http::Response<Body>, errors::Error> {
+    let (builder, body) = buffered_response(hop_headers, response).await?;
+    builder.body(Body::from(body)).map_err(errors::Error::Http)
+}
+
+/// Reply head and whole body of an upstream response.
+async fn buffered_response(
+    hop_headers: &headers::HopHeaderPolicy,
+    response: reqwest::Response,
+) -> Result<(http::response::Builder, Bytes), errors::Error> {
+    let mut builder = http::Response::builder().status(response.status());
+    for (k, v) in hop_headers.remove(response.headers()).iter() {
+        builder = builder.header(k, v);
     }
-    let relative_path = uri
-        .as_str()
-        .trim_start_matches(&base_path)
-        .trim_start_matches('/');
+    Ok((builder, response.bytes().await?))
+}
+
+/// Upstream URI of a request, without query parameters.
+///
+/// Strips `base_path` from the request `uri` and appends what is left to `proxy_address`,
+/// so `/foo/bar` with base path `foo` forwarded to `http://127.0.0.1:8080/` becomes
+/// `http://127.0.0.1:8080/bar`.
+pub fn remove_relative_path(uri: &FullPath, base_path: String, proxy_address: String) -> String {
+    upstream_path(uri.as_str(), base_path, proxy_address)
+}
 
+pub(crate) fn upstream_path(path: &str, base_path: String, proxy_address: String) -> String {
+    let relative_path = relative_path(path, &base_path);
     let proxy_address = proxy_address.trim_end_matches('/');
     format!("{}/{}", proxy_address, relative_path)
 }
 
-/// Checker method to filter hop headers
+/// Request path with `base_path` stripped, without leading slash.
+pub(crate) fn relative_path<'a>(path: &'a str, base_path: &str) -> &'a str {
+    let base_path = match base_path.starts_with('/') {
+        true => base_path.to_string(),
+        false => format!("/{}", base_path),
+    };
+    path.trim_start_matches(base_path.as_str())
+        .trim_start_matches('/')
+}
+
+/// Resolves the `.` and `..` segments of a request path, percent-encoded or not, and collapses
+/// duplicate slashes, so `/foo/./bar//../baz` becomes `/foo/baz`.
 ///
-/// Headers are checked using unicase to avoid case misfunctions
-fn is_hop_header(header_name: &str) -> bool {
-    static HOP_HEADERS: Lazy<Vec<Ascii<&'static str>>> = Lazy::new(|| {
-        vec![
-            Ascii::new("Connection"),
-            Ascii::new("Keep-Alive"),
-            Ascii::new("Proxy-Authenticate"),
-            Ascii::new("Proxy-Authorization"),
-            Ascii::new("Te"),
-            Ascii::new("Trailers"),
-            Ascii::new("Transfer-Encoding"),
-            Ascii::new("Upgrade"),
-        ]
-    });
+/// Encoded slashes (`%2F`) are decoded first, as upstreams may, so they can't hide a `..`
+/// segment. `..` segments never go above the root.
+pub(crate) fn normalize_path(path: &str) -> String {
+    let path = path.replace("%2f", "/").replace("%2F", "/");
+    let mut segments: Vec<&str> = Vec::new();
+    let mut parts = path.trim_start_matches('/').split('/').peekable();
+    while let Some(part) = parts.next() {
+        let last = parts.peek().is_none();
+        match part.to_ascii_lowercase().as_str() {
+            "." | "%2e" => {}
+            ".." | ".%2e" | "%2e." | "%2e%2e" => {
+                segments.pop();
+            }
+            "" if !last => continue,
+            _ => {
+                segments.push(part);
+                continue;
+            }
+        }
+        if last {
+            segments.push("");
+        }
+    }
+    format!("/{}", segments.join("/"))
+}
 
-    HOP_HEADERS.iter().any(|h| h == &header_name)
+/// Compares secrets without leaking the position of the first difference through timing.
+pub(crate) fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |diff, (a, b)| diff | (a ^ b)) == 0
 }
 
-fn remove_hop_headers(headers: &HeaderMap<HeaderValue>) -> HeaderMap<HeaderValue> {
-    headers
+/// Rejects requests whose body length could be read differently along the way.
+///
+/// Requests carrying both `Transfer-Encoding` and `Content-Length`, or several `Content-Length`
+/// values that disagree, are the usual way to smuggle a request past a proxy.
+fn check_framing(headers: &HeaderMap) -> Result<(), errors::Error> {
+    let mut lengths = headers
+        .get_all(http::header::CONTENT_LENGTH)
         .iter()
-        .filter_map(|(k, v)| {
-            if !is_hop_header(k.as_str()) {
-                Some((k.clone(), v.clone()))
-            } else {
-                None
-            }
-        })
-        .collect()
+        .flat_map(|value| value.to_str().unwrap_or(",").split(','))
+        .map(|length| length.trim().parse::<u64>());
+    let first = match lengths.next() {
+        Some(first) => first,
+        None => return Ok(()),
+    };
+    if headers.contains_key(http::header::TRANSFER_ENCODING) {
+        return Err(errors::Error::AmbiguousFraming(
+            "both Transfer-Encoding and Content-Length",
+        ));
+    }
+    let first = first.map_err(|_| errors::Error::AmbiguousFraming("invalid Content-Length"))?;
+    if lengths.any(|length| length != Ok(first)) {
+        return Err(errors::Error::AmbiguousFraming(
+            "conflicting Content-Length values",
+        ));
+    }
+    Ok(())
 }
 
-fn filtered_data_to_request(
+fn check_uri_length(
+    options: &ForwardOptions,
+    proxy_uri: &str,
+    params: &QueryParameters,
+) -> Result<(), errors::Error> {
+    let length = proxy_uri.len() + params.as_ref().map_or(0, |params| params.len() + 1);
+    match options.max_uri_length {
+        Some(max) if length > max => Err(errors::Error::UriTooLong(max)),
+        _ => Ok(()),
+    }
+}
+
+/// Builds the upstream request for the extracted request data.
+///
+/// `proxy_address` is the full upstream URI, as built by
+/// [`remove_relative_path`](fn.remove_relative_path.html), to which the query parameters are
+/// appended. The headers of `hop_headers` and `Content-Length` are removed.
+///
+/// Together with [`proxy_request`](fn.proxy_request.html) and
+/// [`response_to_reply`](fn.response_to_reply.html) it allows custom pipelines:
+/// # Examples
+/// ```rust, no_run
+/// use warp::Filter;
+/// use warp_reverse_proxy::headers::HopHeaderPolicy;
+/// use warp_reverse_proxy::{
+///     errors, extract_request_data_filter, filtered_data_to_request, proxy_request,
+///     remove_relative_path, response_to_reply, Request,
+/// };
+///
+/// async fn pipeline(
+///     client: reqwest::Client,
+///     request: Request,
+/// ) -> Result<warp::http::Response<warp::hyper::Body>, errors::Error> {
+///     let hop_headers = HopHeaderPolicy::default();
+///     let uri = remove_relative_path(&request.0, "hello".into(), "http://127.0.0.1:8080".into());
+///     let mut request = filtered_data_to_request(&client, &hop_headers, uri, request)?;
+///     request.headers_mut().insert("x-custom", "foo".parse().unwrap());
+///     let response = proxy_request(&client, request).await?;
+///     response_to_reply(&hop_headers, response).await
+/// }
+///
+/// let client = reqwest::Client::new();
+/// let app = warp::path!("hello" / ..)
+///     .and(extract_request_data_filter())
+///     .and_then(move |uri, params, method, headers, body| {
+///         let client = client.clone();
+///         async move {
+///             pipeline(client, (uri, params, method, headers, body))
+///                 .await
+///                 .map_err(warp::reject::custom)
+///         }
+///     });
+/// ```
+pub fn filtered_data_to_request(
+    client: &reqwest::Client,
+    hop_headers: &headers::HopHeaderPolicy,
     proxy_address: String,
     request: Request,
 ) -> Result<reqwest::Request, errors::Error> {
@@ -258,10 +926,11 @@ fn filtered_data_to_request(
         proxy_address
     };
 
-    let headers = remove_hop_headers(&headers);
+    let mut headers = hop_headers.remove(&headers);
+    // the body is already buffered, let the client frame it from its actual length
+    headers.remove(http::header::CONTENT_LENGTH);
 
-    CLIENT
-        .get_or_init(default_reqwest_client)
+    client
         .request(method, proxy_uri)
         .headers(headers)
         .body(body)
@@ -270,18 +939,31 @@ fn filtered_data_to_request(
 }
 
 /// Build and send a request to the specified address and request data
-async fn proxy_request(request: reqwest::Request) -> Result<reqwest::Response, errors::Error> {
-    CLIENT
-        .get_or_init(default_reqwest_client)
+pub async fn proxy_request(
+    client: &reqwest::Client,
+    request: reqwest::Request,
+) -> Result<reqwest::Response, errors::Error> {
+    client
         .execute(request)
         .await
         .map_err(errors::Error::Request)
 }
 
-/// Build a default client with redirect policy set to none
-fn default_reqwest_client() -> reqwest::Client {
+/// The shared [`CLIENT`](static.CLIENT.html), initialized with the default one if unset
+pub(crate) fn default_client() -> &'static reqwest::Client {
+    CLIENT.get_or_init(default_reqwest_client)
+}
+
+/// Client builder with redirect policy set to none, resolving through the proxy resolver
+pub(crate) fn client_builder() -> reqwest::ClientBuilder {
     reqwest::Client::builder()
         .redirect(Policy::none())
+        .dns_resolver(std::sync::Arc::new(dns::FamilyResolver(None)))
+}
+
+/// Build a default client with redirect policy set to none
+fn default_reqwest_client() -> reqwest::Client {
+    client_builder()
         .build()
         // we should panic here, it is enforce that the client is needed, and there is no error
         // handling possible on function call, better to stop execution.
@@ -290,12 +972,14 @@ fn default_reqwest_client() -> reqwest::Client {
 
 #[cfg(test)]
 pub mod test {
+    use crate::headers::HopHeaderPolicy;
     use crate::{
-        extract_request_data_filter, filtered_data_to_request, proxy_request, remove_relative_path,
-        reverse_proxy_filter, Request,
+        check_framing, default_client, extract_request_data_filter, filtered_data_to_request,
+        normalize_path, proxy_request, proxy_to, remove_relative_path, reverse_proxy_filter,
+        ForcedUpstream, Request,
     };
     use std::net::SocketAddr;
-    use warp::http::StatusCode;
+    use warp::http::{HeaderMap, HeaderValue, StatusCode};
     use warp::Filter;
 
     fn serve_test_response(path: String, address: SocketAddr) {
@@ -358,6 +1042,8 @@ pub mod test {
         tokio::task::yield_now().await;
         // transform request data into an actual request
         let request = filtered_data_to_request(
+            default_client(),
+            &HopHeaderPolicy::default(),
             remove_relative_path(
                 &request.0,
                 "".to_string(),
@@ -366,7 +1052,7 @@ pub mod test {
             request,
         )
         .unwrap();
-        let response = proxy_request(request).await.unwrap();
+        let response = proxy_request(default_client(), request).await.unwrap();
         assert_eq!(response.status(), StatusCode::OK);
     }
 
@@ -398,4 +1084,160 @@ pub mod test {
 
         assert_eq!(response.status(), StatusCode::OK);
     }
+
+    #[test]
+    fn path_normalization() {
+        for (path, normalized) in [
+            ("/", "/"),
+            ("/foo//bar", "/foo/bar"),
+            ("/foo/./bar/", "/foo/bar/"),
+            ("/foo/bar/..", "/foo/"),
+            ("/foo/%2E%2e/bar", "/bar"),
+            ("/../../etc/passwd", "/etc/passwd"),
+            ("//foo/...", "/foo/..."),
+            ("/api/..%2fadmin", "/admin"),
+            ("/api/%2E%2E%2F%2e%2e%2fadmin/a%2fb", "/admin/a/b"),
+        ] {
+            assert_eq!(normalize_path(path), normalized, "{}", path);
+        }
+    }
+
+    #[test]
+    fn ambiguous_framing_rejected() {
+        let headers = |values: &[(&'static str, &'static str)]| {
+            let mut headers = HeaderMap::new();
+            for (name, value) in values {
+                headers.append(*name, HeaderValue::from_static(value));
+            }
+            headers
+        };
+
+        assert!(check_framing(&headers(&[])).is_ok());
+        assert!(check_framing(&headers(&[("content-length", "3")])).is_ok());
+        assert!(check_framing(&headers(&[
+            ("content-length", "3"),
+            ("content-length", "3")
+        ]))
+        .is_ok());
+        assert!(check_framing(&headers(&[
+            ("content-length", "3"),
+            ("transfer-encoding", "chunked")
+        ]))
+        .is_err());
+        assert!(check_framing(&headers(&[
+            ("content-length", "3"),
+            ("content-length", "4")
+        ]))
+        .is_err());
+        assert!(check_framing(&headers(&[("content-length", "3, 4")])).is_err());
+        assert!(check_framing(&headers(&[("content-length", "three")])).is_err());
+    }
+
+    #[tokio::test]
+    async fn ambiguous_framing_replies_bad_request() {
+        let filter = reverse_proxy_filter("".to_string(), "http://127.0.0.1:1".to_string())
+            .recover(crate::errors::recover);
+
+        let response = warp::test::request()
+            .header("content-length", "3")
+            .header("transfer-encoding", "chunked")
+            .body("foo")
+            .reply(&filter)
+            .await;
+
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn interim_responses_are_skipped() {
+        use std::io::{Read, Write};
+
+        let listener = std::net::TcpListener::bind(("127.0.0.1", 0)).unwrap();
+        let address = listener.local_addr().unwrap();
+        std::thread::spawn(move || {
+            let mut stream = listener.incoming().next().unwrap().unwrap();
+            let mut head = Vec::new();
+            let mut byte = [0];
+            while !head.ends_with(b"\r\n\r\n") && stream.read(&mut byte).unwrap() == 1 {
+                head.push(byte[0]);
+            }
+            let reply = "HTTP/1.1 102 Processing\r\n\r\nHTTP/1.1 199 Custom\r\nx-foo: 1\r\n\r\n\
+                HTTP/1.1 207 Multi-Status\r\ncontent-length: 4\r\n\r\ndone";
+            stream.write_all(reply.as_bytes()).unwrap();
+        });
+
+        let filter = reverse_proxy_filter("".to_string(), format!("http://{}", address));
+        let response = warp::test::request().reply(&filter).await;
+        assert_eq!(response.status(), StatusCode::MULTI_STATUS);
+        assert!(!response.headers().contains_key("x-foo"));
+        assert_eq!(response.body(), "done");
+    }
+
+    #[tokio::test]
+    async fn client_disconnect_cancels_upstream_call() {
+        use std::io::Read;
+
+        let listener = std::net::TcpListener::bind(("127.0.0.1", 0)).unwrap();
+        let address = listener.local_addr().unwrap();
+        let (closed, upstream_closed) = std::sync::mpsc::channel();
+        std::thread::spawn(move || {
+            let mut stream = listener.incoming().next().unwrap().unwrap();
+            // never replies, reading until the proxy drops the connection
+            let mut buffer = [0; 1024];
+            while stream.read(&mut buffer).unwrap_or(0) > 0 {}
+            closed.send(()).unwrap();
+        });
+
+        let filter = reverse_proxy_filter("".to_string(), format!("http://{}", address));
+        let request = warp::test::request().reply(&filter);
+        // dropping the request future, as hyper does when the client disconnects
+        assert!(
+            tokio::time::timeout(std::time::Duration::from_millis(100), request)
+                .await
+                .is_err()
+        );
+        tokio::task::spawn_blocking(move || {
+            upstream_closed
+                .recv_timeout(std::time::Duration::from_secs(2))
+                .unwrap()
+        })
+        .await
+        .unwrap();
+    }
+
+    #[tokio::test]
+    async fn proxy_to_returns_upstream_response() {
+        let (address, server) = warp::serve(
+            warp::path!("bar").map(|| warp::reply::with_header("upstream", "x-foo", "bar")),
+        )
+        .bind_ephemeral(([127, 0, 0, 1], 0));
+        tokio::spawn(server);
+
+        let request = warp::test::request()
+            .path("/foo/bar")
+            .filter(&extract_request_data_filter())
+            .await
+            .unwrap();
+        let response = proxy_to(format!("http://{}", address), "foo".to_string(), request)
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()["x-foo"], "bar");
+        assert_eq!(response.text().await.unwrap(), "upstream");
+    }
+
+    #[tokio::test]
+    async fn forced_upstream_overrides_proxy_address() {
+        let (address, server) =
+            warp::serve(warp::path!("bar").map(|| "forced")).bind_ephemeral(([127, 0, 0, 1], 0));
+        tokio::spawn(server);
+
+        let filter = reverse_proxy_filter("foo".to_string(), "http://127.0.0.1:1".to_string());
+        let response = warp::test::request()
+            .path("/foo/bar")
+            .extension(ForcedUpstream(format!("http://{}", address)))
+            .reply(&filter)
+            .await;
+        assert_eq!(response.body(), "forced");
+    }
 }
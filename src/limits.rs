@@ -0,0 +1,238 @@
+//! Limits protecting the proxy and its upstreams from oversized requests.
+use crate::{audit, errors};
+use futures_util::{future, Stream, TryStreamExt};
+use warp::filters::path::FullPath;
+use warp::http::{header, HeaderMap, Method};
+use warp::hyper::body::{Buf, Bytes};
+use warp::{Filter, Rejection};
+
+type BoxError = Box<dyn std::error::Error + Send + Sync>;
+
+/// Limits on the headers of forwarded requests.
+///
+/// Header sizes are counted as `name: value`, without line terminators. Exceeding any of them
+/// fails the request with [`errors::Error::HeadersTooLarge`], replied as
+/// `431 Request Header Fields Too Large` by the [`Route`](../config/struct.Route.html#method.header_limits)
+/// it is set on. The standalone [`filter`](#method.filter) rejects it instead, for
+/// [`errors::recover`](../errors/fn.recover.html) to reply.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct HeaderLimits {
+    max_total_bytes: Option<usize>,
+    max_header_bytes: Option<usize>,
+    max_count: Option<usize>,
+}
+
+impl HeaderLimits {
+    /// Limits allowing any headers, to be narrowed with the setters.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Maximum size of all the headers together.
+    pub fn max_total_bytes(mut self, bytes: usize) -> Self {
+        self.max_total_bytes = Some(bytes);
+        self
+    }
+
+    /// Maximum size of a single header.
+    pub fn max_header_bytes(mut self, bytes: usize) -> Self {
+        self.max_header_bytes = Some(bytes);
+        self
+    }
+
+    /// Maximum number of headers, counting every value of repeated ones.
+    pub fn max_count(mut self, count: usize) -> Self {
+        self.max_count = Some(count);
+        self
+    }
+
+    /// Checks a header map against the limits.
+    pub fn check(&self, headers: &HeaderMap) -> Result<(), errors::Error> {
+        if let Some(max) = self.max_count {
+            if headers.len() > max {
+                return Err(errors::Error::HeadersTooLarge(format!(
+                    "more than {} headers",
+                    max
+                )));
+            }
+        }
+        let mut total = 0;
+        for (name, value) in headers {
+            let size = name.as_str().len() + 2 + value.len();
+            if let Some(max) = self.max_header_bytes {
+                if size > max {
+                    return Err(errors::Error::HeadersTooLarge(format!(
+                        "header {} is larger than {} bytes",
+                        name, max
+                    )));
+                }
+            }
+            total += size;
+        }
+        match self.max_total_bytes {
+            Some(max) if total > max => Err(errors::Error::HeadersTooLarge(format!(
+                "headers are larger than {} bytes",
+                max
+            ))),
+            _ => Ok(()),
+        }
+    }
+
+    /// Warp filter rejecting requests whose headers exceed the limits.
+    pub fn filter(self) -> impl Filter<Extract = (), Error = Rejection> + Clone {
+        audit::request_line()
+            .and(warp::header::headers_cloned())
+            .and_then(
+                move |method: Method, path: FullPath, headers: HeaderMap| async move {
+                    self.check(&headers).map_err(|e| {
+                        warp::reject::custom(audit::denied(
+                            &method,
+                            path.as_str(),
+                            "header_limits",
+                            e,
+                        ))
+                    })
+                },
+            )
+            .untuple_one()
+    }
+}
+
+/// Limit on the size of forwarded request bodies.
+///
+/// Requests declaring a larger `Content-Length` fail with [`errors::Error::BodyTooLarge`],
+/// replied as `413 Payload Too Large` by the [`Route`](../config/struct.Route.html#method.body_limit)
+/// it is set on. Bodies sent without a length, chunked or over HTTP/2, are cut once they exceed
+/// it: buffered ones are refused the same, streamed ones fail the upstream request, also replied
+/// `413`. The standalone [`filter`](#method.filter) only checks the declared length, and rejects
+/// instead, for [`errors::recover`](../errors/fn.recover.html) to reply.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct BodyLimit {
+    max_bytes: u64,
+}
+
+impl BodyLimit {
+    /// Limits bodies to `max_bytes`.
+    pub fn new(max_bytes: u64) -> Self {
+        Self { max_bytes }
+    }
+
+    pub fn max_bytes(&self) -> u64 {
+        self.max_bytes
+    }
+
+    /// Checks the `Content-Length` of a request.
+    pub fn check(&self, headers: &HeaderMap) -> Result<(), errors::Error> {
+        let length = headers
+            .get(header::CONTENT_LENGTH)
+            .and_then(|length| length.to_str().ok())
+            .and_then(|length| length.parse::<u64>().ok());
+        match length {
+            Some(length) if length > self.max_bytes => {
+                Err(errors::Error::BodyTooLarge(self.max_bytes))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Warp filter rejecting requests declaring a body larger than the limit.
+    pub fn filter(self) -> impl Filter<Extract = (), Error = Rejection> + Clone {
+        audit::request_line()
+            .and(warp::header::headers_cloned())
+            .and_then(
+                move |method: Method, path: FullPath, headers: HeaderMap| async move {
+                    self.check(&headers).map_err(|e| {
+                        warp::reject::custom(audit::denied(&method, path.as_str(), "body_limit", e))
+                    })
+                },
+            )
+            .untuple_one()
+    }
+
+    /// Fails `stream` with [`errors::Error::BodyTooLarge`] once it yields more than the limit.
+    pub(crate) fn limit<S, B, E>(self, stream: S) -> impl Stream<Item = Result<Bytes, BoxError>>
+    where
+        S: Stream<Item = Result<B, E>>,
+        B: Buf,
+        E: Into<BoxError>,
+    {
+        let mut total = 0u64;
+        stream.map_err(Into::into).and_then(move |mut chunk| {
+            let chunk = chunk.copy_to_bytes(chunk.remaining());
+            total += chunk.len() as u64;
+            let result = match total > self.max_bytes {
+                true => Err(errors::Error::BodyTooLarge(self.max_bytes).into()),
+                false => Ok(chunk),
+            };
+            future::ready(result)
+        })
+    }
+
+    /// Filter buffering the request body, extracting [`errors::Error::BodyTooLarge`] instead
+    /// once its declared length or the bytes read exceed the limit. Read failures are rejected.
+    pub(crate) fn buffered(
+        self,
+    ) -> impl Filter<Extract = (Result<Bytes, errors::Error>,), Error = Rejection> + Clone {
+        warp::header::headers_cloned()
+            .and(warp::body::stream())
+            .and_then(move |headers: HeaderMap, stream| async move {
+                if let Err(e) = self.check(&headers) {
+                    return Ok(Err(e));
+                }
+                let body = self.limit(stream).try_fold(Vec::new(), |mut body, chunk| {
+                    body.extend_from_slice(&chunk);
+                    future::ready(Ok(body))
+                });
+                match body.await {
+                    Ok(body) => Ok(Ok(Bytes::from(body))),
+                    Err(e) => match e.downcast::<errors::Error>() {
+                        Ok(e) => Ok(Err(*e)),
+                        Err(_) => Err(warp::reject::reject()),
+                    },
+                }
+            })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::HeaderLimits;
+    use warp::http::StatusCode;
+    use warp::Filter;
+
+    #[tokio::test]
+    async fn header_limits_reply_431() {
+        let filter = HeaderLimits::new()
+            .max_count(2)
+            .max_header_bytes(16)
+            .max_total_bytes(24)
+            .filter()
+            .map(warp::reply)
+            .recover(crate::errors::recover);
+
+        let reply = |headers: &'static [(&'static str, &'static str)]| {
+            let filter = filter.clone();
+            async move {
+                let mut request = warp::test::request();
+                for (name, value) in headers {
+                    request = request.header(*name, *value);
+                }
+                request.reply(&filter).await.status()
+            }
+        };
+
+        assert_eq!(reply(&[("foo", "bar")]).await, StatusCode::OK);
+        assert_eq!(
+            reply(&[("foo", "bar"), ("bar", "baz"), ("baz", "foo")]).await,
+            StatusCode::REQUEST_HEADER_FIELDS_TOO_LARGE
+        );
+        assert_eq!(
+            reply(&[("foo", "a very long value")]).await,
+            StatusCode::REQUEST_HEADER_FIELDS_TOO_LARGE
+        );
+        assert_eq!(
+            reply(&[("foo", "0123456789"), ("bar", "0123456789")]).await,
+            StatusCode::REQUEST_HEADER_FIELDS_TOO_LARGE
+        );
+    }
+}
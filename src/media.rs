@@ -0,0 +1,159 @@
+//! Restriction of the media types forwarded, such as an upload gateway only taking
+//! `multipart/form-data` and JSON.
+use crate::{audit, errors};
+use warp::filters::path::FullPath;
+use warp::http::{header, HeaderMap, Method};
+use warp::{Filter, Rejection};
+
+/// Media types requests with a body may have.
+///
+/// Types are compared ignoring case and parameters, so `application/json` allows
+/// `application/json; charset=utf-8`, and `image/*` allows any image. Requests with a body of
+/// any other type, or of no type at all, fail with [`errors::Error::UnsupportedMediaType`],
+/// replied as `415 Unsupported Media Type` by the
+/// [`Route`](../config/struct.Route.html#method.content_types) it is set on, never by the next
+/// routes. The standalone [`filter`](#method.filter) rejects them instead, for
+/// [`errors::recover`](../errors/fn.recover.html) to reply. Requests without a body always pass:
+/// those with a `Content-Length` of 0, or with neither `Content-Length` nor `Transfer-Encoding`
+/// when their method is `GET` or `HEAD`. HTTP/2 requests may stream a body without either
+/// header, so other methods are checked as having one.
+/// # Examples
+/// ```
+/// use warp_reverse_proxy::config::Route;
+/// use warp_reverse_proxy::media::ContentTypes;
+///
+/// let uploads = Route::new("uploads", "http://storage.local").content_types(
+///     ContentTypes::new()
+///         .allow("multipart/form-data")
+///         .allow("application/json"),
+/// );
+/// ```
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ContentTypes(Vec<String>);
+
+impl ContentTypes {
+    /// Allows no media type, to be extended with [`allow`](#method.allow).
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Allows `media_type`, such as `application/json` or `image/*`.
+    pub fn allow(mut self, media_type: impl Into<String>) -> Self {
+        self.0.push(media_type.into().trim().to_ascii_lowercase());
+        self
+    }
+
+    /// Checks the method and headers of a request.
+    pub fn check(&self, method: &Method, headers: &HeaderMap) -> Result<(), errors::Error> {
+        let has_body = match headers.get(header::CONTENT_LENGTH) {
+            _ if headers.contains_key(header::TRANSFER_ENCODING) => true,
+            Some(length) => length != "0",
+            None => !matches!(*method, Method::GET | Method::HEAD),
+        };
+        if !has_body {
+            return Ok(());
+        }
+        let content_type = headers
+            .get(header::CONTENT_TYPE)
+            .ok_or_else(|| errors::Error::UnsupportedMediaType("missing Content-Type".to_string()))?
+            .to_str()
+            .ok()
+            .and_then(|value| value.split(';').next())
+            .map(|essence| essence.trim().to_ascii_lowercase())
+            .unwrap_or_default();
+        let allowed = self
+            .0
+            .iter()
+            .any(|allowed| match allowed.strip_suffix("/*") {
+                Some(kind) => content_type
+                    .split_once('/')
+                    .is_some_and(|(content_kind, _)| content_kind == kind),
+                None => *allowed == content_type,
+            });
+        match allowed {
+            true => Ok(()),
+            false => Err(errors::Error::UnsupportedMediaType(content_type)),
+        }
+    }
+
+    /// Warp filter rejecting the requests failing the check.
+    pub fn filter(self) -> impl Filter<Extract = (), Error = Rejection> + Clone {
+        audit::request_line()
+            .and(warp::header::headers_cloned())
+            .and_then(move |method: Method, path: FullPath, headers: HeaderMap| {
+                let result = self.check(&method, &headers).map_err(|e| {
+                    warp::reject::custom(audit::denied(&method, path.as_str(), "content_types", e))
+                });
+                async move { result }
+            })
+            .untuple_one()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::ContentTypes;
+    use crate::config::{ProxyConfig, Route};
+    use warp::http::StatusCode;
+    use warp::Filter;
+
+    #[tokio::test]
+    async fn content_types_reply_415() {
+        let (address, server) =
+            warp::serve(warp::any().map(warp::reply)).bind_ephemeral(([127, 0, 0, 1], 0));
+        tokio::spawn(server);
+
+        let (catchall, server) =
+            warp::serve(warp::any().map(|| "catchall")).bind_ephemeral(([127, 0, 0, 1], 0));
+        tokio::spawn(server);
+
+        let filter = ProxyConfig::new(([127, 0, 0, 1], 0).into())
+            .route(
+                Route::new("", format!("http://{}", address)).content_types(
+                    ContentTypes::new()
+                        .allow("multipart/form-data")
+                        .allow("Application/JSON")
+                        .allow("image/*"),
+                ),
+            )
+            .route(Route::new("", format!("http://{}", catchall)))
+            .filter();
+        let reply = |content_type: Option<&'static str>, body: &'static str| {
+            let filter = filter.clone();
+            async move {
+                let mut request = warp::test::request().method("POST").body(body);
+                if let Some(content_type) = content_type {
+                    request = request.header("content-type", content_type);
+                }
+                request.reply(&filter).await.status()
+            }
+        };
+
+        assert_eq!(
+            reply(Some("application/json; charset=utf-8"), "{}").await,
+            StatusCode::OK
+        );
+        assert_eq!(reply(Some("image/png"), "png").await, StatusCode::OK);
+        assert_eq!(reply(None, "").await, StatusCode::OK);
+        assert_eq!(
+            reply(Some("text/plain"), "text").await,
+            StatusCode::UNSUPPORTED_MEDIA_TYPE
+        );
+        assert_eq!(
+            reply(Some("imagery/png"), "png").await,
+            StatusCode::UNSUPPORTED_MEDIA_TYPE
+        );
+        assert_eq!(
+            reply(None, "data").await,
+            StatusCode::UNSUPPORTED_MEDIA_TYPE
+        );
+
+        // Without the body framing headers, as HTTP/2 requests may come.
+        let unframed = |method: &'static str| warp::test::request().method(method).reply(&filter);
+        assert_eq!(unframed("GET").await.status(), StatusCode::OK);
+        assert_eq!(
+            unframed("POST").await.status(),
+            StatusCode::UNSUPPORTED_MEDIA_TYPE
+        );
+    }
+}
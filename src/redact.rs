@@ -0,0 +1,224 @@
+//! Redaction of secrets from the observability data of the proxy.
+//!
+//! The [`REDACTION`] rules are applied to the error messages replied by
+//! [`errors::recover`](../errors/fn.recover.html), to route [`Trace`](../config/struct.Trace.html)s
+//! and, through [`OutboundRequest::redacted`](../struct.OutboundRequest.html#method.redacted), to
+//! dry runs. The [`testing`](../testing/index.html) echo endpoint applies them to the requests it
+//! replies, JSON bodies included with the `json` feature. Applications logging or recording
+//! requests themselves can apply them too.
+use once_cell::sync::{Lazy, OnceCell};
+use warp::http::{header, HeaderMap, HeaderName, HeaderValue};
+
+/// Replacement of redacted values
+pub const REDACTED: &str = "[REDACTED]";
+
+/// Redaction rules used by the proxy.
+///
+/// Unless set, the [`Redaction::new`] defaults are used. It can be overridden calling
+/// `OnceCell::set` as follows:
+/// # Examples
+/// ```
+/// use warp::http::HeaderName;
+/// use warp_reverse_proxy::redact::{Redaction, REDACTION};
+///
+/// let redaction = Redaction::new()
+///     .header(HeaderName::from_static("x-api-key"))
+///     .query_param("session")
+///     .json_field("password");
+/// REDACTION.set(redaction).ok().expect("redaction is set");
+/// ```
+pub static REDACTION: OnceCell<Redaction> = OnceCell::new();
+
+/// Header names, query parameters and JSON fields whose values are redacted.
+///
+/// Names are matched ignoring ASCII case.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Redaction {
+    headers: Vec<HeaderName>,
+    query_params: Vec<String>,
+    json_fields: Vec<String>,
+}
+
+impl Default for Redaction {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Redaction {
+    /// Rules redacting the `Authorization`, `Proxy-Authorization`, `Cookie` and `Set-Cookie`
+    /// headers, and the `access_token`, `api_key`, `signature` and `token` query parameters.
+    pub fn new() -> Self {
+        Self {
+            headers: vec![
+                header::AUTHORIZATION,
+                header::PROXY_AUTHORIZATION,
+                header::COOKIE,
+                header::SET_COOKIE,
+            ],
+            query_params: ["access_token", "api_key", "signature", "token"]
+                .iter()
+                .map(|param| param.to_string())
+                .collect(),
+            json_fields: Vec::new(),
+        }
+    }
+
+    /// Rules redacting nothing, to add rules to from scratch.
+    pub fn empty() -> Self {
+        Self {
+            headers: Vec::new(),
+            query_params: Vec::new(),
+            json_fields: Vec::new(),
+        }
+    }
+
+    pub fn header(mut self, name: HeaderName) -> Self {
+        self.headers.push(name);
+        self
+    }
+
+    pub fn query_param(mut self, name: impl Into<String>) -> Self {
+        self.query_params.push(name.into());
+        self
+    }
+
+    /// Redacts the `name` fields of JSON objects, at any depth.
+    ///
+    /// Only JSON bodies are redacted with these, which needs the `json` feature.
+    pub fn json_field(mut self, name: impl Into<String>) -> Self {
+        self.json_fields.push(name.into());
+        self
+    }
+
+    /// Copy of `headers` with the values of the redacted ones replaced.
+    pub fn headers(&self, headers: &HeaderMap) -> HeaderMap {
+        let mut redacted = headers.clone();
+        for name in &self.headers {
+            if let header::Entry::Occupied(mut entry) = redacted.entry(name) {
+                entry.insert(HeaderValue::from_static(REDACTED));
+            }
+        }
+        redacted
+    }
+
+    /// `uri`, or any text with a query in it, with the values of the redacted query parameters
+    /// replaced.
+    pub fn uri(&self, uri: &str) -> String {
+        let (path, query) = match uri.split_once('?') {
+            Some(parts) => parts,
+            None => return uri.to_string(),
+        };
+        let (query, fragment) = match query.find(['#', ' ', ')']) {
+            Some(end) => query.split_at(end),
+            None => (query, ""),
+        };
+        let query = query
+            .split('&')
+            .map(|pair| match pair.split_once('=') {
+                Some((name, _)) if self.is_redacted_param(name) => format!("{}={}", name, REDACTED),
+                _ => pair.to_string(),
+            })
+            .collect::<Vec<_>>()
+            .join("&");
+        format!("{}?{}{}", path, query, fragment)
+    }
+
+    /// Replaces the values of the redacted fields of `value`.
+    #[cfg(feature = "json")]
+    pub fn json(&self, value: &mut serde_json::Value) {
+        match value {
+            serde_json::Value::Object(fields) => {
+                for (name, field) in fields.iter_mut() {
+                    match self
+                        .json_fields
+                        .iter()
+                        .any(|f| f.eq_ignore_ascii_case(name))
+                    {
+                        true => *field = serde_json::Value::String(REDACTED.to_string()),
+                        false => self.json(field),
+                    }
+                }
+            }
+            serde_json::Value::Array(values) => values.iter_mut().for_each(|v| self.json(v)),
+            _ => {}
+        }
+    }
+
+    fn is_redacted_param(&self, name: &str) -> bool {
+        self.query_params
+            .iter()
+            .any(|param| param.eq_ignore_ascii_case(name))
+    }
+}
+
+/// The [`REDACTION`] rules in use.
+pub(crate) fn current() -> &'static Redaction {
+    static DEFAULT: Lazy<Redaction> = Lazy::new(Redaction::new);
+    REDACTION.get().unwrap_or(&DEFAULT)
+}
+
+#[cfg(test)]
+mod test {
+    use super::Redaction;
+    use crate::config::Route;
+    use warp::http::{HeaderMap, HeaderName, HeaderValue};
+    use warp::Filter;
+
+    #[test]
+    fn headers_and_queries_are_redacted() {
+        let redaction = Redaction::new().header(HeaderName::from_static("x-api-key"));
+        let mut headers = HeaderMap::new();
+        headers.insert("authorization", HeaderValue::from_static("Bearer t0k"));
+        headers.append("x-api-key", HeaderValue::from_static("k1"));
+        headers.append("x-api-key", HeaderValue::from_static("k2"));
+        headers.insert("accept", HeaderValue::from_static("text/plain"));
+        let redacted = redaction.headers(&headers);
+        assert_eq!(redacted["authorization"], "[REDACTED]");
+        assert_eq!(redacted.get_all("x-api-key").iter().count(), 1);
+        assert_eq!(redacted["accept"], "text/plain");
+
+        assert_eq!(
+            redaction.uri("http://api.local/a?page=2&Token=s3cr3t&flag#top"),
+            "http://api.local/a?page=2&Token=[REDACTED]&flag#top"
+        );
+        assert_eq!(
+            redaction.uri("error for url (http://api.local/?api_key=k): refused"),
+            "error for url (http://api.local/?api_key=[REDACTED]): refused"
+        );
+        assert_eq!(Redaction::empty().uri("/?token=t"), "/?token=t");
+    }
+
+    #[cfg(feature = "json")]
+    #[test]
+    fn json_fields_are_redacted() {
+        let mut value = serde_json::json!({
+            "user": "ops",
+            "password": "secret",
+            "nested": [{ "Password": "secret", "keep": 1 }]
+        });
+        Redaction::new().json_field("password").json(&mut value);
+        assert_eq!(
+            value,
+            serde_json::json!({
+                "user": "ops",
+                "password": "[REDACTED]",
+                "nested": [{ "Password": "[REDACTED]", "keep": 1 }]
+            })
+        );
+    }
+
+    #[tokio::test]
+    async fn error_replies_are_redacted() {
+        let filter = Route::new("", "http://127.0.0.1:1")
+            .filter()
+            .recover(crate::errors::recover);
+        let response = warp::test::request()
+            .path("/login?access_token=s3cr3t")
+            .reply(&filter)
+            .await;
+        let body = String::from_utf8_lossy(response.body());
+        assert!(body.contains("access_token=[REDACTED]"), "{}", body);
+        assert!(!body.contains("s3cr3t"));
+    }
+}
@@ -0,0 +1,143 @@
+//! Reloading a JSON configuration file on `SIGHUP`.
+//!
+//! [`on_sighup`] listens for the signal, re-reads and validates the file, and pushes the new
+//! [`ProxyConfig`] through a watch channel, so a server can rebuild its filter from it. A file
+//! that can't be read, doesn't parse, or fails [`ProxyConfig::validate`] with errors is logged
+//! and not applied: the channel keeps the last working configuration.
+use crate::config::ProxyConfig;
+use crate::errors::Error;
+use futures_util::future::{self, Either};
+use std::path::{Path, PathBuf};
+use tokio::signal::unix::{signal, SignalKind};
+use tokio::sync::watch;
+
+/// Reads, parses and validates a configuration file.
+///
+/// Validation warnings are logged, errors fail the load with [`Error::Config`].
+pub fn load(path: &Path) -> Result<ProxyConfig, Error> {
+    let json = std::fs::read_to_string(path)
+        .map_err(|e| Error::Config(format!("couldn't read {}: {}", path.display(), e)))?;
+    let config = ProxyConfig::from_json(&json)?;
+    let mut errors = Vec::new();
+    for diagnostic in config.validate() {
+        match diagnostic.is_error() {
+            true => errors.push(diagnostic.to_string()),
+            false => log::warn!("{}: {}", path.display(), diagnostic),
+        }
+    }
+    match errors.is_empty() {
+        true => Ok(config),
+        false => Err(Error::Config(errors.join(", "))),
+    }
+}
+
+/// Starts listening for `SIGHUP`, reloading `path` on each signal.
+///
+/// The returned receiver holds `config` until a reload succeeds. Listening stops once every
+/// receiver is dropped. Must be called within a tokio runtime, fails if the signal handler
+/// can't be registered.
+///
+/// # Examples
+/// ```no_run
+/// use warp_reverse_proxy::{config::ProxyConfig, reload};
+///
+/// #[tokio::main]
+/// async fn main() {
+///     let path = std::path::Path::new("proxy.json");
+///     let config = reload::load(path).unwrap();
+///     let mut configs = reload::on_sighup(path, config).unwrap();
+///     while configs.changed().await.is_ok() {
+///         let config: ProxyConfig = configs.borrow_and_update().clone();
+///         // rebuild the served filter from `config`
+///     }
+/// }
+/// ```
+pub fn on_sighup(
+    path: impl Into<PathBuf>,
+    config: ProxyConfig,
+) -> std::io::Result<watch::Receiver<ProxyConfig>> {
+    let path = path.into();
+    let mut hangups = signal(SignalKind::hangup())?;
+    let (sender, receiver) = watch::channel(config);
+    tokio::spawn(async move {
+        loop {
+            let hangup = future::select(Box::pin(hangups.recv()), Box::pin(sender.closed()));
+            if let Either::Left((None, _)) | Either::Right(_) = hangup.await {
+                break;
+            }
+            match load(&path) {
+                Ok(config) => {
+                    log::info!("{}: configuration reloaded", path.display());
+                    if sender.send(config).is_err() {
+                        break;
+                    }
+                }
+                Err(e) => log::error!("{}: reload not applied: {}", path.display(), e),
+            }
+        }
+    });
+    Ok(receiver)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::time::Duration;
+
+    fn write(path: &Path, address: &str) {
+        let json = format!(
+            r#"{{"address": "{}", "routes": [{{"upstream": "http://127.0.0.1:8080"}}]}}"#,
+            address
+        );
+        std::fs::write(path, json).unwrap();
+    }
+
+    fn hangup() {
+        let status = std::process::Command::new("kill")
+            .args(["-HUP", &std::process::id().to_string()])
+            .status()
+            .unwrap();
+        assert!(status.success());
+    }
+
+    #[test]
+    fn load_refuses_broken_files() {
+        let path = std::env::temp_dir().join(format!("reload-load-{}.json", std::process::id()));
+        assert!(matches!(load(&path), Err(Error::Config(_))));
+        std::fs::write(&path, r#"{"address": "127.0.0.1:3030", "route": []}"#).unwrap();
+        assert!(load(&path).is_err());
+        let duplicated = r#"{"address": "127.0.0.1:3030", "routes": [
+            {"upstream": "http://127.0.0.1:8080"}, {"upstream": "http://127.0.0.1:8081"}
+        ]}"#;
+        std::fs::write(&path, duplicated).unwrap();
+        assert!(matches!(load(&path), Err(Error::Config(message)) if message.contains("route 1")));
+        write(&path, "127.0.0.1:3031");
+        assert_eq!(
+            load(&path).unwrap().address(),
+            ([127, 0, 0, 1], 3031).into()
+        );
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn hangups_reload_valid_files_only() {
+        let path = std::env::temp_dir().join(format!("reload-sighup-{}.json", std::process::id()));
+        write(&path, "127.0.0.1:3030");
+        let mut configs = on_sighup(&path, load(&path).unwrap()).unwrap();
+
+        std::fs::write(&path, "{").unwrap();
+        hangup();
+        let unchanged = tokio::time::timeout(Duration::from_millis(300), configs.changed()).await;
+        assert!(unchanged.is_err());
+        assert_eq!(configs.borrow().address(), ([127, 0, 0, 1], 3030).into());
+
+        write(&path, "127.0.0.1:3031");
+        hangup();
+        tokio::time::timeout(Duration::from_secs(5), configs.changed())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(configs.borrow().address(), ([127, 0, 0, 1], 3031).into());
+        std::fs::remove_file(&path).unwrap();
+    }
+}
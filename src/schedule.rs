@@ -0,0 +1,208 @@
+//! Time windows during which routes take requests, for planned maintenance.
+//!
+//! A route [`active`](../config/struct.Route.html#method.active) during a window only matches
+//! requests inside it, letting them fall through to the next routes otherwise. Placing a
+//! maintenance route first shifts the traffic to its upstream, or to the reply of its
+//! [`decide`](../config/struct.Route.html#method.decide) hook, for the window alone.
+use crate::errors;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+const DAY: u64 = 24 * 60 * 60;
+
+/// Day of the week, in UTC.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Weekday {
+    Monday,
+    Tuesday,
+    Wednesday,
+    Thursday,
+    Friday,
+    Saturday,
+    Sunday,
+}
+
+impl Weekday {
+    const ALL: [Weekday; 7] = [
+        Weekday::Monday,
+        Weekday::Tuesday,
+        Weekday::Wednesday,
+        Weekday::Thursday,
+        Weekday::Friday,
+        Weekday::Saturday,
+        Weekday::Sunday,
+    ];
+
+    /// Parses English day names, full or three letters long, ignoring case.
+    pub fn parse(day: &str) -> Result<Self, errors::Error> {
+        let day = day.to_ascii_lowercase();
+        Self::ALL
+            .iter()
+            .copied()
+            .find(|weekday| {
+                let name = format!("{:?}", weekday).to_ascii_lowercase();
+                name == day || name[..3] == day
+            })
+            .ok_or_else(|| errors::Error::Config(format!("invalid weekday {:?}", day)))
+    }
+
+    /// Day of the week of a Unix day number, 1970-01-01 being a Thursday.
+    fn of_day(day: u64) -> Self {
+        Self::ALL[((day + 3) % 7) as usize]
+    }
+}
+
+/// A time window, either fixed or repeated every day.
+/// # Examples
+/// ```
+/// use std::time::{Duration, UNIX_EPOCH};
+/// use warp_reverse_proxy::schedule::{Weekday, Window};
+///
+/// let release = Window::between(
+///     UNIX_EPOCH + Duration::from_secs(1_800_000_000),
+///     UNIX_EPOCH + Duration::from_secs(1_800_003_600),
+/// );
+/// let nightly = Window::daily("23:30", "01:00")
+///     .unwrap()
+///     .weekdays(&[Weekday::Saturday, Weekday::Sunday]);
+/// // Saturday 2027-01-02 23:45 UTC
+/// assert!(nightly.contains(UNIX_EPOCH + Duration::from_secs(1_798_933_500)));
+/// assert!(!release.contains(UNIX_EPOCH + Duration::from_secs(1_798_933_500)));
+/// ```
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Window(Kind);
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum Kind {
+    Between(SystemTime, SystemTime),
+    Daily {
+        /// Seconds since midnight UTC
+        start: u64,
+        end: u64,
+        weekdays: Vec<Weekday>,
+    },
+}
+
+impl Window {
+    /// From `start`, included, to `end`, excluded.
+    pub fn between(start: SystemTime, end: SystemTime) -> Self {
+        Self(Kind::Between(start, end))
+    }
+
+    /// Every day from `start` to `end`, both `HH:MM` in UTC.
+    ///
+    /// An `end` earlier than `start` ends the next day, so `23:30` to `01:00` spans midnight.
+    pub fn daily(start: &str, end: &str) -> Result<Self, errors::Error> {
+        Ok(Self(Kind::Daily {
+            start: time_of_day(start)?,
+            end: time_of_day(end)?,
+            weekdays: Vec::new(),
+        }))
+    }
+
+    /// Restricts a daily window to the ones starting on `weekdays`.
+    pub fn weekdays(mut self, days: &[Weekday]) -> Self {
+        if let Kind::Daily { weekdays, .. } = &mut self.0 {
+            weekdays.extend_from_slice(days);
+        }
+        self
+    }
+
+    /// Whether `time` falls inside the window.
+    pub fn contains(&self, time: SystemTime) -> bool {
+        match &self.0 {
+            Kind::Between(start, end) => *start <= time && time < *end,
+            Kind::Daily {
+                start,
+                end,
+                weekdays,
+            } => {
+                let seconds = time
+                    .duration_since(UNIX_EPOCH)
+                    .unwrap_or(Duration::ZERO)
+                    .as_secs();
+                let (day, now) = (seconds / DAY, seconds % DAY);
+                let started_on = match start <= end {
+                    true if (*start..*end).contains(&now) => day,
+                    false if now >= *start => day,
+                    false if now < *end => match day.checked_sub(1) {
+                        Some(day) => day,
+                        None => return false,
+                    },
+                    _ => return false,
+                };
+                weekdays.is_empty() || weekdays.contains(&Weekday::of_day(started_on))
+            }
+        }
+    }
+}
+
+fn time_of_day(time: &str) -> Result<u64, errors::Error> {
+    let invalid =
+        || errors::Error::Config(format!("invalid time of day {:?}, expected HH:MM", time));
+    let (hours, minutes) = time.split_once(':').ok_or_else(invalid)?;
+    let hours: u64 = hours.parse().map_err(|_| invalid())?;
+    let minutes: u64 = minutes.parse().map_err(|_| invalid())?;
+    if hours > 23 || minutes > 59 {
+        return Err(invalid());
+    }
+    Ok(hours * 3600 + minutes * 60)
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Weekday, Window};
+    use crate::config::{ProxyConfig, Route};
+    use std::time::{Duration, SystemTime, UNIX_EPOCH};
+    use warp::Filter;
+
+    #[test]
+    fn daily_windows() {
+        // Monday 2027-01-04 00:00 UTC
+        let monday = UNIX_EPOCH + Duration::from_secs(1_799_020_800);
+        let at =
+            |hours: u64, minutes: u64| monday + Duration::from_secs(hours * 3600 + minutes * 60);
+        let window = Window::daily("02:00", "04:30").unwrap();
+        assert!(window.contains(at(2, 0)));
+        assert!(window.contains(at(4, 29)));
+        assert!(!window.contains(at(4, 30)));
+        assert!(!window.contains(at(1, 59)));
+
+        let overnight = Window::daily("23:00", "01:00")
+            .unwrap()
+            .weekdays(&[Weekday::Sunday]);
+        assert!(overnight.contains(at(0, 30)));
+        assert!(!overnight.contains(at(23, 30)));
+        assert!(overnight.contains(at(23, 30) - Duration::from_secs(24 * 3600)));
+
+        assert_eq!(Weekday::parse("SAT").unwrap(), Weekday::Saturday);
+        assert!(Weekday::parse("someday").is_err());
+        assert!(Window::daily("24:00", "01:00").is_err());
+        assert!(Window::daily("1", "01:00").is_err());
+    }
+
+    #[tokio::test]
+    async fn routes_shift_during_windows() {
+        let upstream = |body: &'static str| {
+            let (address, server) =
+                warp::serve(warp::any().map(move || body)).bind_ephemeral(([127, 0, 0, 1], 0));
+            tokio::spawn(server);
+            format!("http://{}", address)
+        };
+        let (maintenance, main) = (upstream("maintenance"), upstream("main"));
+        let now = SystemTime::now();
+        let hour = Duration::from_secs(3600);
+        let config = |window: Window| {
+            ProxyConfig::new(([127, 0, 0, 1], 0).into())
+                .route(Route::new("", &maintenance).active(window))
+                .route(Route::new("", &main))
+        };
+
+        let current = config(Window::between(now - hour, now + hour));
+        let response = warp::test::request().reply(&current.filter()).await;
+        assert_eq!(response.body(), "maintenance");
+        let past = config(Window::between(now - 2 * hour, now - hour));
+        let response = warp::test::request().reply(&past.filter()).await;
+        assert_eq!(response.body(), "main");
+        assert!(past.validate().iter().all(|d| !d.is_error()));
+    }
+}
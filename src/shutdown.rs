@@ -0,0 +1,242 @@
+//! Graceful shutdown support for proxied requests.
+//!
+//! A [`Shutdown`] handle tracks every request going through the filters it wraps, including
+//! streamed response bodies, so a server can stop taking new proxied requests and wait for the
+//! in-flight ones before exiting.
+use crate::{audit, errors};
+use futures_util::Stream;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::Duration;
+use tokio::sync::watch;
+use tokio_util::sync::{CancellationToken, WaitForCancellationFutureOwned};
+use warp::filters::path::FullPath;
+use warp::http;
+use warp::hyper::body::Bytes;
+use warp::hyper::Body;
+use warp::{Filter, Rejection};
+
+type BoxError = Box<dyn std::error::Error + Send + Sync>;
+
+/// Shutdown handle shared by the tracked filters and the server shutdown signal.
+///
+/// # Examples
+/// ```no_run
+/// use std::time::Duration;
+/// use warp::Filter;
+/// use warp_reverse_proxy::{reverse_proxy_filter, shutdown::Shutdown};
+///
+/// #[tokio::main]
+/// async fn main() {
+///     let shutdown = Shutdown::new();
+///     let app = shutdown.track(reverse_proxy_filter(
+///         "".to_string(),
+///         "http://127.0.0.1:8080/".to_string(),
+///     ));
+///
+///     let (stop, stopped) = tokio::sync::oneshot::channel::<()>();
+///     let drain = shutdown.clone();
+///     let (_, server) =
+///         warp::serve(app).bind_with_graceful_shutdown(([0, 0, 0, 0], 3030), async move {
+///             stopped.await.ok();
+///             drain.drain(Duration::from_secs(30)).await;
+///         });
+///     tokio::spawn(server);
+///     // ...
+///     stop.send(()).ok();
+/// }
+/// ```
+#[derive(Clone)]
+pub struct Shutdown {
+    inner: Arc<Inner>,
+}
+
+struct Inner {
+    draining: AtomicBool,
+    in_flight: watch::Sender<usize>,
+    deadline: CancellationToken,
+}
+
+/// Marker held while a tracked request (and its response body) is alive.
+struct InFlight {
+    inner: Arc<Inner>,
+}
+
+impl Drop for InFlight {
+    fn drop(&mut self) {
+        self.inner.in_flight.send_modify(|count| *count -= 1);
+    }
+}
+
+impl Default for Shutdown {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Shutdown {
+    pub fn new() -> Self {
+        Self {
+            inner: Arc::new(Inner {
+                draining: AtomicBool::new(false),
+                in_flight: watch::Sender::new(0),
+                deadline: CancellationToken::new(),
+            }),
+        }
+    }
+
+    /// Whether [`drain`](Shutdown::drain) has been called.
+    pub fn is_draining(&self) -> bool {
+        self.inner.draining.load(Ordering::SeqCst)
+    }
+
+    /// Number of tracked requests whose response has not completed yet.
+    pub fn in_flight(&self) -> usize {
+        *self.inner.in_flight.borrow()
+    }
+
+    /// Wraps a proxy filter so its requests are tracked by this handle.
+    ///
+    /// Once draining, new requests are rejected with [`errors::Error::ShuttingDown`].
+    pub fn track<F>(
+        &self,
+        filter: F,
+    ) -> impl Filter<Extract = (http::Response<Body>,), Error = Rejection> + Clone
+    where
+        F: Filter<Extract = (http::Response<Body>,), Error = Rejection> + Clone + Send + Sync,
+    {
+        let shutdown = self.clone();
+        audit::request_line()
+            .and_then(move |method, path: FullPath| {
+                let entered = shutdown.enter();
+                async move {
+                    entered.ok_or_else(|| {
+                        let error = errors::Error::ShuttingDown;
+                        warp::reject::custom(audit::denied(
+                            &method,
+                            path.as_str(),
+                            "shutdown",
+                            error,
+                        ))
+                    })
+                }
+            })
+            .and(filter)
+            .map(|in_flight: InFlight, response: http::Response<Body>| {
+                let deadline = in_flight.inner.deadline.clone().cancelled_owned();
+                response.map(|body| {
+                    Body::wrap_stream(Tracked {
+                        body,
+                        deadline: Box::pin(deadline),
+                        _in_flight: in_flight,
+                    })
+                })
+            })
+    }
+
+    /// Stops accepting new tracked requests and resolves once the in-flight ones are done.
+    ///
+    /// Responses still streaming after `deadline` are cut off before resolving.
+    pub async fn drain(&self, deadline: Duration) {
+        self.inner.draining.store(true, Ordering::SeqCst);
+        let mut in_flight = self.inner.in_flight.subscribe();
+        if tokio::time::timeout(deadline, in_flight.wait_for(|count| *count == 0))
+            .await
+            .is_err()
+        {
+            self.inner.deadline.cancel();
+        }
+    }
+
+    fn enter(&self) -> Option<InFlight> {
+        if self.is_draining() {
+            return None;
+        }
+        self.inner.in_flight.send_modify(|count| *count += 1);
+        Some(InFlight {
+            inner: self.inner.clone(),
+        })
+    }
+}
+
+struct Tracked {
+    body: Body,
+    deadline: Pin<Box<WaitForCancellationFutureOwned>>,
+    _in_flight: InFlight,
+}
+
+impl Stream for Tracked {
+    type Item = Result<Bytes, BoxError>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.get_mut();
+        if this.deadline.as_mut().poll(cx).is_ready() {
+            return Poll::Ready(Some(Err(errors::Error::ShuttingDown.into())));
+        }
+        Pin::new(&mut this.body)
+            .poll_next(cx)
+            .map(|chunk| chunk.map(|chunk| chunk.map_err(BoxError::from)))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Shutdown;
+    use std::time::Duration;
+    use warp::http;
+    use warp::hyper::body::{to_bytes, Bytes};
+    use warp::hyper::Body;
+    use warp::Filter;
+
+    fn streaming_filter(
+        body: std::sync::Arc<std::sync::Mutex<Option<Body>>>,
+    ) -> impl Filter<Extract = (http::Response<Body>,), Error = warp::Rejection> + Clone {
+        warp::any().and_then(move || {
+            let body = body.lock().unwrap().take().unwrap();
+            async move { Ok::<_, warp::Rejection>(http::Response::new(body)) }
+        })
+    }
+
+    #[tokio::test]
+    async fn drain_waits_for_streams() {
+        let (mut sender, body) = Body::channel();
+        let shutdown = Shutdown::new();
+        let filter = shutdown.track(streaming_filter(std::sync::Arc::new(
+            std::sync::Mutex::new(Some(body)),
+        )));
+
+        let response = warp::test::request().filter(&filter).await.unwrap();
+        assert_eq!(shutdown.in_flight(), 1);
+
+        let drain = tokio::spawn({
+            let shutdown = shutdown.clone();
+            async move { shutdown.drain(Duration::from_secs(5)).await }
+        });
+        tokio::task::yield_now().await;
+        assert!(shutdown.is_draining());
+        assert!(warp::test::request().filter(&filter).await.is_err());
+
+        sender.send_data(Bytes::from("foo")).await.unwrap();
+        drop(sender);
+        assert_eq!(to_bytes(response.into_body()).await.unwrap(), "foo");
+        drain.await.unwrap();
+        assert_eq!(shutdown.in_flight(), 0);
+    }
+
+    #[tokio::test]
+    async fn drain_deadline_cuts_streams() {
+        let (_sender, body) = Body::channel();
+        let shutdown = Shutdown::new();
+        let filter = shutdown.track(streaming_filter(std::sync::Arc::new(
+            std::sync::Mutex::new(Some(body)),
+        )));
+
+        let response = warp::test::request().filter(&filter).await.unwrap();
+        shutdown.drain(Duration::from_millis(20)).await;
+        assert!(to_bytes(response.into_body()).await.is_err());
+        assert_eq!(shutdown.in_flight(), 0);
+    }
+}
@@ -0,0 +1,252 @@
+//! Helpers for long-lived (streamed) proxied responses.
+use crate::errors;
+use futures_util::Stream;
+use std::future::Future;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::Duration;
+use tokio::time::{sleep, Instant, Sleep};
+use warp::http;
+use warp::hyper::body::Bytes;
+use warp::hyper::Body;
+
+type BoxError = Box<dyn std::error::Error + Send + Sync>;
+
+/// Closes a streamed response if no bytes are received from the upstream for `timeout`.
+///
+/// The request body is fully read before forwarding, so once the response is streaming the only
+/// traffic left is from the upstream. When that stream stays silent for `timeout` the body
+/// errors with [`errors::Error::IdleTimeout`], which drops both the client connection and the
+/// upstream one.
+///
+/// # Examples
+/// ```
+/// use std::time::Duration;
+/// use warp::Filter;
+/// use warp_reverse_proxy::{reverse_proxy_filter, streaming::with_idle_timeout};
+///
+/// let app = warp::path!("events" / ..).and(
+///     reverse_proxy_filter("".to_string(), "http://127.0.0.1:8080/".to_string())
+///         .map(|response| with_idle_timeout(response, Duration::from_secs(30))),
+/// );
+/// ```
+pub fn with_idle_timeout(
+    response: http::Response<Body>,
+    timeout: Duration,
+) -> http::Response<Body> {
+    response.map(|body| Body::wrap_stream(IdleTimeout::new(body, timeout)))
+}
+
+/// Chunk-wise transformation of a streamed body, for changes that don't need the whole body
+/// such as scrubbing log lines or filtering SSE events.
+///
+/// Chunks arrive as the upstream sends them, so a transform matching across chunk boundaries
+/// has to keep the unfinished part itself and release it on a later call or in
+/// [`finish`](StreamTransform::finish). The bytes are the ones sent by the upstream, still
+/// compressed if the response has a `Content-Encoding`.
+pub trait StreamTransform: Send + 'static {
+    /// Transforms a chunk of the body, an empty result holds the data back.
+    fn transform(&mut self, chunk: Bytes) -> Bytes;
+
+    /// Bytes to append once the upstream body is over.
+    fn finish(&mut self) -> Bytes {
+        Bytes::new()
+    }
+}
+
+/// Applies a [`StreamTransform`] to a streamed response body.
+///
+/// As the body length can change, `Content-Length` is removed (so the response goes out
+/// chunked) along with the validators of the original bytes: `ETag`, `Content-MD5` and `Digest`.
+///
+/// # Examples
+/// ```
+/// use warp::hyper::body::Bytes;
+/// use warp::Filter;
+/// use warp_reverse_proxy::reverse_proxy_filter;
+/// use warp_reverse_proxy::streaming::{with_stream_transform, StreamTransform};
+///
+/// struct Uppercase;
+///
+/// impl StreamTransform for Uppercase {
+///     fn transform(&mut self, chunk: Bytes) -> Bytes {
+///         chunk.to_ascii_uppercase().into()
+///     }
+/// }
+///
+/// let app = warp::path!("logs" / ..).and(
+///     reverse_proxy_filter("".to_string(), "http://127.0.0.1:8080/".to_string())
+///         .map(|response| with_stream_transform(response, Uppercase)),
+/// );
+/// ```
+pub fn with_stream_transform<T: StreamTransform>(
+    response: http::Response<Body>,
+    transform: T,
+) -> http::Response<Body> {
+    let (mut parts, body) = response.into_parts();
+    for header in [
+        http::header::CONTENT_LENGTH,
+        http::header::ETAG,
+        http::header::HeaderName::from_static("content-md5"),
+        http::header::HeaderName::from_static("digest"),
+    ] {
+        parts.headers.remove(header);
+    }
+    let body = Body::wrap_stream(Transformed {
+        body,
+        transform: Some(transform),
+    });
+    http::Response::from_parts(parts, body)
+}
+
+struct Transformed<T> {
+    body: Body,
+    /// Taken once finished
+    transform: Option<T>,
+}
+
+// the transform is never pinned, only called through `&mut`
+impl<T> Unpin for Transformed<T> {}
+
+impl<T: StreamTransform> Stream for Transformed<T> {
+    type Item = Result<Bytes, BoxError>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.get_mut();
+        loop {
+            let transform = match this.transform.as_mut() {
+                Some(transform) => transform,
+                None => return Poll::Ready(None),
+            };
+            let chunk = match Pin::new(&mut this.body).poll_next(cx) {
+                Poll::Pending => return Poll::Pending,
+                Poll::Ready(Some(Err(e))) => return Poll::Ready(Some(Err(e.into()))),
+                Poll::Ready(Some(Ok(chunk))) => transform.transform(chunk),
+                Poll::Ready(None) => {
+                    let last = transform.finish();
+                    this.transform = None;
+                    last
+                }
+            };
+            // empty chunks are skipped rather than sent, they would end a chunked body
+            if !chunk.is_empty() {
+                return Poll::Ready(Some(Ok(chunk)));
+            }
+        }
+    }
+}
+
+struct IdleTimeout {
+    body: Body,
+    timeout: Duration,
+    sleep: Pin<Box<Sleep>>,
+}
+
+impl IdleTimeout {
+    fn new(body: Body, timeout: Duration) -> Self {
+        Self {
+            body,
+            timeout,
+            sleep: Box::pin(sleep(timeout)),
+        }
+    }
+}
+
+impl Stream for IdleTimeout {
+    type Item = Result<Bytes, BoxError>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.get_mut();
+        match Pin::new(&mut this.body).poll_next(cx) {
+            Poll::Ready(Some(chunk)) => {
+                this.sleep.as_mut().reset(Instant::now() + this.timeout);
+                Poll::Ready(Some(chunk.map_err(BoxError::from)))
+            }
+            Poll::Ready(None) => Poll::Ready(None),
+            Poll::Pending => match this.sleep.as_mut().poll(cx) {
+                Poll::Ready(()) => {
+                    Poll::Ready(Some(Err(errors::Error::IdleTimeout(this.timeout).into())))
+                }
+                Poll::Pending => Poll::Pending,
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{with_idle_timeout, with_stream_transform, StreamTransform};
+    use std::time::Duration;
+    use warp::http;
+    use warp::hyper::body::{to_bytes, Bytes};
+    use warp::hyper::Body;
+
+    #[tokio::test]
+    async fn idle_stream_is_closed() {
+        let (_sender, body) = Body::channel();
+        let response = with_idle_timeout(http::Response::new(body), Duration::from_millis(20));
+        assert!(to_bytes(response.into_body()).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn active_stream_is_kept() {
+        let (mut sender, body) = Body::channel();
+        let response = with_idle_timeout(http::Response::new(body), Duration::from_millis(50));
+        tokio::spawn(async move {
+            for _ in 0..3 {
+                tokio::time::sleep(Duration::from_millis(20)).await;
+                sender.send_data(Bytes::from("foo")).await.unwrap();
+            }
+        });
+        let body = to_bytes(response.into_body()).await.unwrap();
+        assert_eq!(body, Bytes::from("foofoofoo"));
+    }
+
+    /// Drops every line containing `secret`, lines may span chunks
+    struct Scrub(Vec<u8>);
+
+    impl StreamTransform for Scrub {
+        fn transform(&mut self, chunk: Bytes) -> Bytes {
+            self.0.extend_from_slice(&chunk);
+            let end = match self.0.iter().rposition(|b| *b == b'\n') {
+                Some(end) => end + 1,
+                None => return Bytes::new(),
+            };
+            let lines: Vec<u8> = self.0.drain(..end).collect();
+            scrub(&lines)
+        }
+
+        fn finish(&mut self) -> Bytes {
+            scrub(&std::mem::take(&mut self.0))
+        }
+    }
+
+    fn scrub(lines: &[u8]) -> Bytes {
+        String::from_utf8_lossy(lines)
+            .split_inclusive('\n')
+            .filter(|line| !line.contains("secret"))
+            .collect::<String>()
+            .into()
+    }
+
+    #[tokio::test]
+    async fn stream_transform_across_chunks() {
+        let (mut sender, body) = Body::channel();
+        let response = http::Response::builder()
+            .header("content-length", "30")
+            .header("etag", "\"foo\"")
+            .body(body)
+            .unwrap();
+        let response = with_stream_transform(response, Scrub(Vec::new()));
+        assert!(response.headers().get("content-length").is_none());
+        assert!(response.headers().get("etag").is_none());
+
+        tokio::spawn(async move {
+            for chunk in ["foo\nsec", "ret\nbar", "\nthe secret", " end"] {
+                sender.send_data(Bytes::from(chunk)).await.unwrap();
+            }
+        });
+        let body = to_bytes(response.into_body()).await.unwrap();
+        assert_eq!(body, Bytes::from("foo\nbar\n"));
+    }
+}
@@ -0,0 +1,171 @@
+//! Path templates capturing request path segments, such as `/users/{id}/files/{name}`.
+//!
+//! A [`Route`](../config/struct.Route.html) with a template only takes the requests whose path,
+//! past the route base path, matches it. The captured segments can then build the upstream path
+//! and headers sent upstream, with `{name}` placeholders.
+use crate::errors;
+
+/// Segments of a path, each matching literally or capturing a value.
+/// # Examples
+/// ```
+/// use warp_reverse_proxy::template::PathTemplate;
+///
+/// let template = PathTemplate::parse("/users/{id}/files/{name}").unwrap();
+/// let captures = template.captures("/users/42/files/report.pdf").unwrap();
+/// assert_eq!(captures.get("id"), Some("42"));
+/// assert_eq!(captures.expand("/v2/blobs/{id}-{name}"), "/v2/blobs/42-report.pdf");
+/// assert!(template.captures("/users/42").is_none());
+/// ```
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PathTemplate(Vec<Segment>);
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum Segment {
+    Literal(String),
+    Capture(String),
+}
+
+/// Values captured by a [`PathTemplate`], in template order.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Captures(Vec<(String, String)>);
+
+impl PathTemplate {
+    /// Parses a template, failing on unbalanced braces, empty or repeated names, and captures
+    /// sharing a segment with other text.
+    pub fn parse(template: &str) -> Result<Self, errors::Error> {
+        let invalid = |reason: &str| {
+            errors::Error::Config(format!("invalid path template {:?}: {}", template, reason))
+        };
+        let mut segments = Vec::new();
+        for segment in template.trim_start_matches('/').split('/') {
+            let segment = match segment.strip_prefix('{') {
+                Some(name) => {
+                    let name = name
+                        .strip_suffix('}')
+                        .ok_or_else(|| invalid("unclosed capture"))?;
+                    if name.is_empty() || name.contains(['{', '}']) {
+                        return Err(invalid("bad capture name"));
+                    }
+                    if segments.contains(&Segment::Capture(name.to_string())) {
+                        return Err(invalid("repeated capture name"));
+                    }
+                    Segment::Capture(name.to_string())
+                }
+                None if segment.contains(['{', '}']) => {
+                    return Err(invalid("captures must take whole segments"))
+                }
+                None => Segment::Literal(segment.to_string()),
+            };
+            segments.push(segment);
+        }
+        Ok(Self(segments))
+    }
+
+    /// Captured values, if `path` matches the template.
+    ///
+    /// Values are the raw path segments, still percent-encoded.
+    pub fn captures(&self, path: &str) -> Option<Captures> {
+        let mut parts = path.trim_start_matches('/').split('/');
+        let mut captures = Vec::new();
+        for segment in &self.0 {
+            let part = parts.next()?;
+            match segment {
+                Segment::Literal(literal) if literal == part => {}
+                Segment::Capture(name) if !part.is_empty() => {
+                    captures.push((name.clone(), part.to_string()))
+                }
+                _ => return None,
+            }
+        }
+        match parts.next() {
+            Some(_) => None,
+            None => Some(Captures(captures)),
+        }
+    }
+}
+
+impl Captures {
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.0
+            .iter()
+            .find(|(n, _)| n == name)
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// Replaces the `{name}` placeholders of `template` with the captured values.
+    ///
+    /// Placeholders of unknown names are left as is.
+    pub fn expand(&self, template: &str) -> String {
+        let mut expanded = String::with_capacity(template.len());
+        let mut rest = template;
+        while let Some(start) = rest.find('{') {
+            expanded.push_str(&rest[..start]);
+            let placeholder = &rest[start..];
+            match placeholder.find('}').and_then(|end| {
+                let value = self.get(&placeholder[1..end])?;
+                Some((value, end))
+            }) {
+                Some((value, end)) => {
+                    expanded.push_str(value);
+                    rest = &placeholder[end + 1..];
+                }
+                None => {
+                    expanded.push('{');
+                    rest = &placeholder[1..];
+                }
+            }
+        }
+        expanded.push_str(rest);
+        expanded
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::PathTemplate;
+    use crate::config::Route;
+    use warp::http::{HeaderName, StatusCode};
+    use warp::Filter;
+
+    #[test]
+    fn templates_parse_and_capture() {
+        for invalid in ["/users/{id", "/users/{}", "/users/id-{id}", "/{id}/{id}"] {
+            assert!(PathTemplate::parse(invalid).is_err(), "{}", invalid);
+        }
+        let template = PathTemplate::parse("users/{id}").unwrap();
+        assert!(template.captures("/users/").is_none());
+        assert!(template.captures("/users/42/files").is_none());
+        assert!(template.captures("/accounts/42").is_none());
+        let captures = template.captures("users/42").unwrap();
+        assert_eq!(captures.expand("{id}/{name}/{"), "42/{name}/{");
+    }
+
+    #[tokio::test]
+    async fn templates_rewrite_upstream_requests() {
+        let (address, server) = warp::serve(
+            warp::path::full()
+                .and(warp::header::optional::<String>("x-user-id"))
+                .map(|path: warp::path::FullPath, user: Option<String>| {
+                    format!("{} {}", path.as_str(), user.unwrap_or_default())
+                }),
+        )
+        .bind_ephemeral(([127, 0, 0, 1], 0));
+        tokio::spawn(server);
+
+        let filter = Route::new("api", format!("http://{}/storage", address))
+            .path_template(PathTemplate::parse("/users/{id}/files/{name}").unwrap())
+            .upstream_path("/v2/blobs/{id}/{name}")
+            .capture_header(HeaderName::from_static("x-user-id"), "{id}")
+            .filter();
+        let response = warp::test::request()
+            .path("/api/users/42/files/report.pdf?download")
+            .reply(&filter)
+            .await;
+        assert_eq!(response.body(), "/storage/v2/blobs/42/report.pdf 42");
+        let response = warp::test::request()
+            .path("/api/users/42")
+            .reply(&filter)
+            .await;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
+}
@@ -0,0 +1,263 @@
+//! Endpoints served by the proxy itself, for synthetic monitoring and integration testing of
+//! its clients.
+//!
+//! Under a prefix, `__proxy` by default:
+//! * `/__proxy/echo` replies the request it got: request line, headers and body, with the
+//!   [`REDACTION`](../redact/static.REDACTION.html) rules applied.
+//! * `/__proxy/status/{code}` replies an empty response with the `code` status.
+//! * `/__proxy/delay/{ms}` replies `200 OK` after `ms` milliseconds.
+//!
+//! Invalid status codes, `1xx` included, and delays longer than the
+//! [`max_delay`](struct.TestEndpoints.html#method.max_delay) are replied `400 Bad Request`.
+//! Bodies larger than the [`max_echo_bytes`](struct.TestEndpoints.html#method.max_echo_bytes)
+//! are replied `413 Payload Too Large`.
+use crate::errors;
+use crate::limits::BodyLimit;
+use std::time::Duration;
+use warp::filters::path::FullPath;
+use warp::filters::BoxedFilter;
+use warp::http::{self, HeaderMap, Method, StatusCode};
+use warp::hyper::body::Bytes;
+use warp::hyper::Body;
+use warp::{Filter, Reply};
+
+/// Testing endpoints settings.
+/// # Examples
+/// ```
+/// use std::time::Duration;
+/// use warp_reverse_proxy::config::{ProxyConfig, Route};
+/// use warp_reverse_proxy::testing::TestEndpoints;
+///
+/// let config = ProxyConfig::new(([0, 0, 0, 0], 3030).into())
+///     .test_endpoints(TestEndpoints::new().prefix("_monitoring").max_delay(Duration::from_secs(2)))
+///     .route(Route::new("", "http://127.0.0.1:8080"));
+/// ```
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TestEndpoints {
+    prefix: String,
+    max_delay: Duration,
+    max_echo_bytes: u64,
+}
+
+impl Default for TestEndpoints {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl TestEndpoints {
+    /// Endpoints under `/__proxy`, with delays up to 10 seconds and echoed bodies up to 64KiB.
+    pub fn new() -> Self {
+        Self {
+            prefix: "__proxy".to_string(),
+            max_delay: Duration::from_secs(10),
+            max_echo_bytes: 64 * 1024,
+        }
+    }
+
+    /// Path the endpoints are served under, such as `_monitoring` or `internal/testing`.
+    pub fn prefix(mut self, prefix: impl Into<String>) -> Self {
+        self.prefix = prefix.into().trim_matches('/').to_string();
+        self
+    }
+
+    /// Longest delay clients can ask for.
+    pub fn max_delay(mut self, max: Duration) -> Self {
+        self.max_delay = max;
+        self
+    }
+
+    /// Largest body the echo endpoint reads and replies.
+    pub fn max_echo_bytes(mut self, bytes: u64) -> Self {
+        self.max_echo_bytes = bytes;
+        self
+    }
+
+    /// Filter serving the endpoints, rejecting any other request with `not_found`.
+    pub fn filter(&self) -> BoxedFilter<(http::Response<Body>,)> {
+        let max_delay = self.max_delay;
+        let echo = warp::path("echo")
+            .and(warp::path::end())
+            .and(warp::method())
+            .and(warp::path::full())
+            .and(warp::query::raw().or(warp::any().map(String::new)).unify())
+            .and(warp::header::headers_cloned())
+            .and(BodyLimit::new(self.max_echo_bytes).buffered())
+            .map(|method, path, query, headers, body| match body {
+                Ok(body) => echo(method, path, query, headers, body),
+                Err(e) => errors::reply(&e),
+            });
+        let status = warp::path!("status" / u16).map(|code| match StatusCode::from_u16(code) {
+            Ok(status) if !status.is_informational() => {
+                warp::reply::with_status(warp::reply(), status).into_response()
+            }
+            _ => bad_request(format!("invalid status code {}", code)),
+        });
+        let delay = warp::path!("delay" / u64).and_then(move |millis| async move {
+            let delay = Duration::from_millis(millis);
+            if delay > max_delay {
+                return Ok::<_, warp::Rejection>(bad_request(format!(
+                    "delay longer than {}ms",
+                    max_delay.as_millis()
+                )));
+            }
+            tokio::time::sleep(delay).await;
+            Ok(format!("delayed {}ms", millis).into_response())
+        });
+        self.prefix
+            .split('/')
+            .filter(|segment| !segment.is_empty())
+            .fold(warp::any().boxed(), |filter, segment| {
+                filter.and(warp::path(segment.to_string())).boxed()
+            })
+            .and(echo.or(status).unify().or(delay).unify())
+            .boxed()
+    }
+}
+
+fn echo(
+    method: Method,
+    path: FullPath,
+    query: String,
+    headers: HeaderMap,
+    body: Bytes,
+) -> http::Response<Body> {
+    let redaction = crate::redact::current();
+    let mut echo = match query.is_empty() {
+        true => format!("{} {}\n", method, path.as_str()),
+        false => format!(
+            "{} {}\n",
+            method,
+            redaction.uri(&format!("{}?{}", path.as_str(), query))
+        ),
+    };
+    #[cfg(feature = "json")]
+    let body = redacted_json(redaction, &headers, body);
+    for (name, value) in &redaction.headers(&headers) {
+        echo.push_str(&format!(
+            "{}: {}\n",
+            name,
+            String::from_utf8_lossy(value.as_bytes())
+        ));
+    }
+    echo.push('\n');
+    let mut echo = echo.into_bytes();
+    echo.extend_from_slice(&body);
+    http::Response::new(Body::from(echo))
+}
+
+/// `body` with the redacted JSON fields replaced, when it is a JSON document holding any.
+#[cfg(feature = "json")]
+fn redacted_json(redaction: &crate::redact::Redaction, headers: &HeaderMap, body: Bytes) -> Bytes {
+    let is_json = headers
+        .get(http::header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.split(';').next())
+        .is_some_and(|essence| essence.trim().to_ascii_lowercase().ends_with("json"));
+    let mut value = match is_json {
+        true => match serde_json::from_slice::<serde_json::Value>(&body) {
+            Ok(value) => value,
+            Err(_) => return body,
+        },
+        false => return body,
+    };
+    let original = value.clone();
+    redaction.json(&mut value);
+    match value == original {
+        true => body,
+        false => Bytes::from(value.to_string()),
+    }
+}
+
+fn bad_request(message: String) -> http::Response<Body> {
+    warp::reply::with_status(message, StatusCode::BAD_REQUEST).into_response()
+}
+
+#[cfg(test)]
+mod test {
+    use super::TestEndpoints;
+    use crate::config::{ProxyConfig, Route};
+    use std::time::Duration;
+    use warp::http::StatusCode;
+    use warp::Filter;
+
+    #[tokio::test]
+    async fn test_endpoints_reply_locally() {
+        let (address, server) =
+            warp::serve(warp::any().map(|| "upstream")).bind_ephemeral(([127, 0, 0, 1], 0));
+        tokio::spawn(server);
+
+        let filter = ProxyConfig::new(([127, 0, 0, 1], 0).into())
+            .test_endpoints(
+                TestEndpoints::new()
+                    .max_delay(Duration::from_millis(50))
+                    .max_echo_bytes(5),
+            )
+            .route(Route::new("", format!("http://{}", address)))
+            .filter();
+        let reply = |method: &'static str, path: &'static str| {
+            let filter = filter.clone();
+            async move {
+                warp::test::request()
+                    .method(method)
+                    .path(path)
+                    .header("x-foo", "bar")
+                    .body("hello")
+                    .reply(&filter)
+                    .await
+            }
+        };
+
+        let response = reply("POST", "/__proxy/echo?a=1").await;
+        let echo = String::from_utf8_lossy(response.body()).to_string();
+        assert!(echo.starts_with("POST /__proxy/echo?a=1\n"), "{}", echo);
+        assert!(echo.contains("\nx-foo: bar\n"), "{}", echo);
+        assert!(echo.ends_with("\n\nhello"), "{}", echo);
+        let response = warp::test::request()
+            .method("POST")
+            .path("/__proxy/echo")
+            .body("hello!")
+            .reply(&filter)
+            .await;
+        assert_eq!(response.status(), StatusCode::PAYLOAD_TOO_LARGE);
+        let response = warp::test::request()
+            .path("/__proxy/echo?token=s3cr3t")
+            .header("authorization", "Bearer s3cr3t")
+            .header("cookie", "session=s3cr3t")
+            .reply(&filter)
+            .await;
+        let echo = String::from_utf8_lossy(response.body()).to_string();
+        assert!(echo.contains("token=[REDACTED]"), "{}", echo);
+        assert!(echo.contains("\nauthorization: [REDACTED]\n"), "{}", echo);
+        assert!(!echo.contains("s3cr3t"), "{}", echo);
+        #[cfg(feature = "json")]
+        {
+            let redaction = crate::redact::Redaction::new().json_field("password");
+            let mut headers = warp::http::HeaderMap::new();
+            let body = warp::hyper::body::Bytes::from(r#"{"user": "ops", "password": "s3cr3t"}"#);
+            let redacted = super::redacted_json(&redaction, &headers, body.clone());
+            assert_eq!(redacted, body);
+            headers.insert("content-type", "application/json".parse().unwrap());
+            let redacted = super::redacted_json(&redaction, &headers, body);
+            assert_eq!(redacted, r#"{"password":"[REDACTED]","user":"ops"}"#);
+        }
+        assert_eq!(
+            reply("GET", "/__proxy/status/503").await.status(),
+            StatusCode::SERVICE_UNAVAILABLE
+        );
+        assert_eq!(
+            reply("GET", "/__proxy/status/102").await.status(),
+            StatusCode::BAD_REQUEST
+        );
+        assert_eq!(
+            reply("GET", "/__proxy/delay/10").await.body(),
+            "delayed 10ms"
+        );
+        assert_eq!(
+            reply("GET", "/__proxy/delay/60").await.status(),
+            StatusCode::BAD_REQUEST
+        );
+        assert_eq!(reply("GET", "/__proxy/other").await.body(), "upstream");
+        assert_eq!(reply("GET", "/__proxyecho").await.body(), "upstream");
+    }
+}
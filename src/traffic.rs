@@ -0,0 +1,154 @@
+//! Accounting of the bytes routes exchange with their upstreams, for capacity planning and
+//! chargeback.
+//!
+//! A [`Traffic`] collects the request and response body bytes of the routes
+//! [`traffic`](../config/struct.Route.html#method.traffic) is set on, per upstream address.
+//! Giving each route its own collector accounts for them separately, sharing one adds them up.
+//! The proxy exposes no metrics itself: applications read the counts and report them.
+use futures_util::TryStreamExt;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use warp::http;
+use warp::hyper::body::Bytes;
+use warp::hyper::Body;
+
+/// Body bytes exchanged with an upstream.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct ByteCount {
+    /// Request body bytes sent upstream
+    pub request_bytes: u64,
+    /// Response body bytes relayed to clients
+    pub response_bytes: u64,
+}
+
+/// Byte counts per upstream address, shared by its clones.
+/// # Examples
+/// ```
+/// use warp_reverse_proxy::config::Route;
+/// use warp_reverse_proxy::traffic::Traffic;
+///
+/// let uploads = Traffic::new();
+/// let route = Route::new("uploads", "http://storage.local").traffic(uploads.clone());
+/// for (upstream, count) in uploads.upstreams() {
+///     println!("{}: {} bytes in", upstream, count.request_bytes);
+/// }
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct Traffic(Arc<Mutex<HashMap<String, Arc<Counter>>>>);
+
+impl Traffic {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Counts per upstream address, sorted by address.
+    pub fn upstreams(&self) -> Vec<(String, ByteCount)> {
+        let mut counts: Vec<_> = self
+            .0
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .iter()
+            .map(|(upstream, counter)| (upstream.clone(), counter.count()))
+            .collect();
+        counts.sort_by(|(a, _), (b, _)| a.cmp(b));
+        counts
+    }
+
+    /// Counts of all upstreams added up.
+    pub fn total(&self) -> ByteCount {
+        self.upstreams()
+            .into_iter()
+            .fold(ByteCount::default(), |total, (_, count)| ByteCount {
+                request_bytes: total.request_bytes + count.request_bytes,
+                response_bytes: total.response_bytes + count.response_bytes,
+            })
+    }
+
+    pub(crate) fn counter(&self, upstream: &str) -> Arc<Counter> {
+        self.0
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .entry(upstream.to_string())
+            .or_default()
+            .clone()
+    }
+}
+
+/// Byte counts of one upstream.
+#[derive(Debug, Default)]
+pub(crate) struct Counter {
+    request_bytes: AtomicU64,
+    response_bytes: AtomicU64,
+}
+
+impl Counter {
+    pub(crate) fn add_request(&self, bytes: u64) {
+        self.request_bytes.fetch_add(bytes, Ordering::Relaxed);
+    }
+
+    /// Counts the body bytes of `reply` as they are relayed.
+    pub(crate) fn count_response(
+        self: Arc<Self>,
+        reply: http::Response<Body>,
+    ) -> http::Response<Body> {
+        reply.map(|body| {
+            Body::wrap_stream(TryStreamExt::inspect_ok(body, move |chunk: &Bytes| {
+                self.response_bytes
+                    .fetch_add(chunk.len() as u64, Ordering::Relaxed);
+            }))
+        })
+    }
+
+    fn count(&self) -> ByteCount {
+        ByteCount {
+            request_bytes: self.request_bytes.load(Ordering::Relaxed),
+            response_bytes: self.response_bytes.load(Ordering::Relaxed),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{ByteCount, Traffic};
+    use crate::config::{BodyMode, Route};
+    use warp::Filter;
+
+    #[tokio::test]
+    async fn routes_count_bytes_per_upstream() {
+        let (address, server) = warp::serve(
+            warp::body::bytes().map(|body: bytes::Bytes| format!("received {} bytes", body.len())),
+        )
+        .bind_ephemeral(([127, 0, 0, 1], 0));
+        tokio::spawn(server);
+        let upstream = format!("http://{}", address);
+
+        let traffic = Traffic::new();
+        let buffered = Route::new("buffered", &upstream).traffic(traffic.clone());
+        let streamed = Route::new("streamed", &upstream)
+            .request_body(BodyMode::Streamed)
+            .traffic(traffic.clone());
+        let filter = buffered.filter().or(streamed.filter());
+        for path in ["/buffered", "/streamed"] {
+            let response = warp::test::request()
+                .method("POST")
+                .path(path)
+                .body("hello")
+                .reply(&filter)
+                .await;
+            assert_eq!(response.body(), "received 5 bytes");
+        }
+
+        let counts = traffic.upstreams();
+        assert_eq!(counts.len(), 1);
+        assert_eq!(counts[0].0, upstream);
+        assert_eq!(
+            traffic.total(),
+            ByteCount {
+                request_bytes: 10,
+                response_bytes: 32,
+            }
+        );
+    }
+}
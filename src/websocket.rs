@@ -0,0 +1,455 @@
+//! WebSocket proxying of route upgrade requests.
+//!
+//! A [`Route`](../config/struct.Route.html#method.websocket) with a [`WebSocket`] relays the
+//! WebSocket upgrades it takes to its upstream, whose `http` and `https` addresses are
+//! connected to as `ws` and `wss`. The upstream handshake goes through the route policies like
+//! any forwarded request: its path, headers and subprotocols are those the request would be
+//! forwarded with. An upstream refusing the handshake has its reply relayed, and one that can't
+//! be reached is replied `502 Bad Gateway`, before the client connection is upgraded.
+//!
+//! Text and binary messages are relayed both ways, pings being answered on each side. Close
+//! frames are forwarded with their code and reason. When either side goes away without one,
+//! the other is closed on its behalf: the client with `1011` when the upstream connection
+//! fails, the upstream with `1001` when the client one does. Either way the end of the
+//! connection is reported to the [`on_close`](struct.WebSocket.html#method.on_close) hooks.
+//!
+//! `wss` upstreams need the `default-tls` feature.
+use crate::errors;
+use futures_util::future::{self, Either};
+use futures_util::{SinkExt, StreamExt};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio_tungstenite::tungstenite::client::IntoClientRequest;
+use tokio_tungstenite::tungstenite::protocol::frame::coding::CloseCode;
+use tokio_tungstenite::tungstenite::protocol::CloseFrame;
+use tokio_tungstenite::tungstenite::{self, http as tungstenite_http};
+use warp::filters::ws::{Message, Ws};
+use warp::filters::BoxedFilter;
+use warp::http::{self, header, HeaderName, HeaderValue};
+use warp::hyper::Body;
+use warp::{Filter, Reply};
+
+/// Code reported for close frames without one
+const NO_STATUS: u16 = 1005;
+/// Code the upstream is closed with when the client goes away
+const GOING_AWAY: u16 = 1001;
+/// Code the client is closed with when the upstream connection fails
+const UPSTREAM_ERROR: u16 = 1011;
+/// How long the other side has to answer a close before being dropped
+const CLOSE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Request headers of the client handshake, not forwarded to the upstream one
+static HANDSHAKE_HEADERS: [HeaderName; 5] = [
+    header::HOST,
+    header::SEC_WEBSOCKET_KEY,
+    header::SEC_WEBSOCKET_VERSION,
+    header::SEC_WEBSOCKET_EXTENSIONS,
+    header::SEC_WEBSOCKET_ACCEPT,
+];
+
+type CloseHook = Arc<dyn Fn(&CloseEvent) + Send + Sync>;
+
+/// Side of a proxied WebSocket connection.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Side {
+    Client,
+    Upstream,
+}
+
+/// End of a proxied WebSocket connection, as reported to the
+/// [`on_close`](struct.WebSocket.html#method.on_close) hooks.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct CloseEvent {
+    /// Upstream URL, `ws` or `wss`
+    pub upstream: String,
+    /// Side that ended the connection
+    pub side: Side,
+    /// Close code that side sent, `1005` if none, or the one sent on its behalf when it failed
+    pub code: u16,
+    pub reason: String,
+    /// Why the connection failed, when it ended without a close frame
+    pub error: Option<String>,
+}
+
+/// WebSocket settings of a route.
+/// # Examples
+/// ```
+/// use warp_reverse_proxy::config::Route;
+/// use warp_reverse_proxy::websocket::WebSocket;
+///
+/// let route = Route::new("live", "http://events.local").websocket(WebSocket::new().on_close(
+///     |event| println!("{:?} closed {} with {}", event.side, event.upstream, event.code),
+/// ));
+/// ```
+#[derive(Clone, Default)]
+pub struct WebSocket {
+    on_close: Vec<CloseHook>,
+}
+
+impl std::fmt::Debug for WebSocket {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("WebSocket")
+            .field("on_close", &self.on_close.len())
+            .finish()
+    }
+}
+
+impl WebSocket {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Calls `hook` once per proxied connection, when it ends.
+    pub fn on_close<F>(mut self, hook: F) -> Self
+    where
+        F: Fn(&CloseEvent) + Send + Sync + 'static,
+    {
+        self.on_close.push(Arc::new(hook));
+        self
+    }
+
+    /// Connects to the upstream `request` would be sent as and relays the client upgrade to it,
+    /// or replies why it can't.
+    pub(crate) async fn upgrade(&self, ws: Ws, request: &reqwest::Request) -> http::Response<Body> {
+        let url = request.url().as_str();
+        let url = match url.strip_prefix("http") {
+            Some(rest) => format!("ws{}", rest),
+            None => url.to_string(),
+        };
+        let handshake = match handshake(&url, request.headers()) {
+            Ok(handshake) => handshake,
+            Err(e) => return errors::reply(&e),
+        };
+        let (upstream, response) = match tokio_tungstenite::connect_async(handshake).await {
+            Ok(connected) => connected,
+            Err(tungstenite::Error::Http(response)) => return refused(response),
+            Err(e) => {
+                log::warn!("websocket connection to {} failed: {}", url, e);
+                return errors::reply(&errors::Error::WebSocket(e.to_string()));
+            }
+        };
+        let protocol = response
+            .headers()
+            .get(tungstenite_http::header::SEC_WEBSOCKET_PROTOCOL)
+            .and_then(|protocol| HeaderValue::from_bytes(protocol.as_bytes()).ok());
+        let hooks = self.on_close.clone();
+        let mut reply = ws
+            .on_upgrade(move |client| relay(client, upstream, url, hooks))
+            .into_response();
+        if let Some(protocol) = protocol {
+            reply
+                .headers_mut()
+                .insert(header::SEC_WEBSOCKET_PROTOCOL, protocol);
+        }
+        reply
+    }
+}
+
+/// Extracts the WebSocket upgrade of a request, if it asks for one.
+pub(crate) fn upgrade_filter() -> BoxedFilter<(Option<Ws>,)> {
+    warp::ws()
+        .map(Some)
+        .or(warp::any().map(|| None))
+        .unify()
+        .boxed()
+}
+
+/// Upstream handshake request to `url` with the forwarded headers.
+fn handshake(
+    url: &str,
+    headers: &http::HeaderMap,
+) -> Result<tungstenite_http::Request<()>, errors::Error> {
+    let mut handshake = url
+        .into_client_request()
+        .map_err(|e| errors::Error::WebSocket(e.to_string()))?;
+    for (name, value) in headers {
+        if HANDSHAKE_HEADERS.contains(name) {
+            continue;
+        }
+        let name = tungstenite_http::HeaderName::from_bytes(name.as_str().as_bytes());
+        let value = tungstenite_http::HeaderValue::from_bytes(value.as_bytes());
+        if let (Ok(name), Ok(value)) = (name, value) {
+            handshake.headers_mut().append(name, value);
+        }
+    }
+    Ok(handshake)
+}
+
+/// Reply relaying an upstream handshake refusal.
+fn refused(response: tungstenite_http::Response<Option<Vec<u8>>>) -> http::Response<Body> {
+    let (parts, body) = response.into_parts();
+    let mut reply = http::Response::new(Body::from(body.unwrap_or_default()));
+    *reply.status_mut() =
+        http::StatusCode::from_u16(parts.status.as_u16()).unwrap_or(http::StatusCode::BAD_GATEWAY);
+    for (name, value) in &parts.headers {
+        let name = HeaderName::from_bytes(name.as_str().as_bytes());
+        let value = HeaderValue::from_bytes(value.as_bytes());
+        if let (Ok(name), Ok(value)) = (name, value) {
+            reply.headers_mut().append(name, value);
+        }
+    }
+    reply
+}
+
+/// Relays messages between the client and the upstream until the connection ends.
+async fn relay<S>(
+    client: warp::ws::WebSocket,
+    upstream: tokio_tungstenite::WebSocketStream<S>,
+    url: String,
+    hooks: Vec<CloseHook>,
+) where
+    S: tokio::io::AsyncRead + tokio::io::AsyncWrite + Unpin,
+{
+    let (mut client_tx, mut client_rx) = client.split();
+    let (mut upstream_tx, mut upstream_rx) = upstream.split();
+    let reported = AtomicBool::new(false);
+    let report = |side: Side, code: u16, reason: String, error: Option<String>| {
+        if reported.swap(true, Ordering::SeqCst) {
+            return;
+        }
+        let event = CloseEvent {
+            upstream: url.clone(),
+            side,
+            code,
+            reason,
+            error,
+        };
+        match &event.error {
+            Some(error) => log::warn!(
+                "websocket to {} failed on the {:?} side: {}",
+                url,
+                side,
+                error
+            ),
+            None => log::debug!(
+                "websocket to {} closed by the {:?} with {}",
+                url,
+                side,
+                code
+            ),
+        }
+        for hook in &hooks {
+            hook(&event);
+        }
+    };
+
+    let to_upstream = async {
+        let mut closed = false;
+        loop {
+            let message = match client_rx.next().await {
+                Some(Ok(message)) => message,
+                Some(Err(_)) | None if closed => break,
+                failed => {
+                    let error = failed.and_then(Result::err).map_or_else(
+                        || "connection closed without a close frame".to_string(),
+                        |e| e.to_string(),
+                    );
+                    let reason = "client went away";
+                    report(Side::Client, GOING_AWAY, reason.to_string(), Some(error));
+                    let _ = upstream_tx.send(close_frame(GOING_AWAY, reason)).await;
+                    break;
+                }
+            };
+            if message.is_close() {
+                let (code, reason) = message.close_frame().unwrap_or((NO_STATUS, ""));
+                report(Side::Client, code, reason.to_string(), None);
+                let _ = upstream_tx.send(close_frame(code, reason)).await;
+                closed = true;
+                continue;
+            }
+            let message = if message.is_text() {
+                tungstenite::Message::Text(message.to_str().unwrap_or_default().to_string())
+            } else if message.is_binary() {
+                tungstenite::Message::Binary(message.into_bytes())
+            } else {
+                continue;
+            };
+            if upstream_tx.send(message).await.is_err() {
+                break;
+            }
+        }
+    };
+    let to_client = async {
+        let mut closed = false;
+        loop {
+            let message = match upstream_rx.next().await {
+                Some(Ok(message)) => message,
+                Some(Err(_)) | None if closed => break,
+                failed => {
+                    let error = failed.and_then(Result::err).map_or_else(
+                        || "connection closed without a close frame".to_string(),
+                        |e| e.to_string(),
+                    );
+                    let reason = "upstream connection failed";
+                    report(
+                        Side::Upstream,
+                        UPSTREAM_ERROR,
+                        reason.to_string(),
+                        Some(error),
+                    );
+                    let _ = client_tx
+                        .send(Message::close_with(UPSTREAM_ERROR, reason))
+                        .await;
+                    break;
+                }
+            };
+            let message = match message {
+                tungstenite::Message::Text(text) => Message::text(text),
+                tungstenite::Message::Binary(bytes) => Message::binary(bytes),
+                tungstenite::Message::Close(frame) => {
+                    let (code, reason) = frame.map_or((NO_STATUS, String::new()), |frame| {
+                        (u16::from(frame.code), frame.reason.into_owned())
+                    });
+                    report(Side::Upstream, code, reason.clone(), None);
+                    let _ = client_tx
+                        .send(match code {
+                            NO_STATUS => Message::close(),
+                            _ => Message::close_with(code, reason),
+                        })
+                        .await;
+                    closed = true;
+                    continue;
+                }
+                _ => continue,
+            };
+            if client_tx.send(message).await.is_err() {
+                break;
+            }
+        }
+    };
+
+    let (to_upstream, to_client) = (Box::pin(to_upstream), Box::pin(to_client));
+    let rest = match future::select(to_upstream, to_client).await {
+        Either::Left((_, to_client)) => Either::Left(to_client),
+        Either::Right((_, to_upstream)) => Either::Right(to_upstream),
+    };
+    // the other side answering the close, or failing, ends it too
+    let _ = tokio::time::timeout(CLOSE_TIMEOUT, rest).await;
+}
+
+/// Upstream close message with `code`, none for `1005`.
+fn close_frame(code: u16, reason: &str) -> tungstenite::Message {
+    tungstenite::Message::Close((code != NO_STATUS).then(|| CloseFrame {
+        code: CloseCode::from(code),
+        reason: reason.to_string().into(),
+    }))
+}
+
+#[cfg(test)]
+mod test {
+    use super::{CloseEvent, Side, WebSocket};
+    use crate::config::Route;
+    use futures_util::{SinkExt, StreamExt};
+    use std::sync::{Arc, Mutex};
+    use tokio_tungstenite::tungstenite::protocol::frame::coding::CloseCode;
+    use tokio_tungstenite::tungstenite::protocol::CloseFrame;
+    use tokio_tungstenite::tungstenite::{Error, Message};
+    use warp::filters::ws::{self, Ws};
+    use warp::Filter;
+
+    #[tokio::test]
+    async fn close_frames_and_failures_are_relayed() {
+        let closes = Arc::new(Mutex::new(Vec::new()));
+        let received = closes.clone();
+        let upstream = warp::ws().map(move |ws: Ws| {
+            let received = received.clone();
+            ws.on_upgrade(|socket| async move {
+                let (mut tx, mut rx) = socket.split();
+                while let Some(Ok(message)) = rx.next().await {
+                    if let Some((code, reason)) = message.close_frame() {
+                        received.lock().unwrap().push((code, reason.to_string()));
+                    }
+                    let reply = match message.to_str() {
+                        Ok("close") => ws::Message::close_with(4001u16, "done"),
+                        Ok("crash") => return,
+                        _ => message,
+                    };
+                    let _ = tx.send(reply).await;
+                }
+            })
+        });
+        let (upstream, server) = warp::serve(upstream).bind_ephemeral(([127, 0, 0, 1], 0));
+        tokio::spawn(server);
+
+        let events = Arc::new(Mutex::new(Vec::<CloseEvent>::new()));
+        let recorded = events.clone();
+        let websocket = WebSocket::new().on_close(move |event| {
+            recorded.lock().unwrap().push(event.clone());
+        });
+        let route = Route::new("live", format!("http://{}", upstream)).websocket(websocket);
+        let (proxy, server) = warp::serve(route.filter()).bind_ephemeral(([127, 0, 0, 1], 0));
+        tokio::spawn(server);
+        let connect = || tokio_tungstenite::connect_async(format!("ws://{}/live", proxy));
+
+        let (mut client, _) = connect().await.unwrap();
+        client.send(Message::text("hello")).await.unwrap();
+        assert_eq!(
+            client.next().await.unwrap().unwrap(),
+            Message::text("hello")
+        );
+        client.send(Message::text("close")).await.unwrap();
+        let close = Message::Close(Some(CloseFrame {
+            code: CloseCode::from(4001),
+            reason: "done".into(),
+        }));
+        assert_eq!(client.next().await.unwrap().unwrap(), close);
+
+        let (mut client, _) = connect().await.unwrap();
+        client.send(Message::text("crash")).await.unwrap();
+        match client.next().await.unwrap().unwrap() {
+            Message::Close(Some(frame)) => assert_eq!(u16::from(frame.code), 1011),
+            message => panic!("unexpected {:?}", message),
+        }
+
+        let (mut client, _) = connect().await.unwrap();
+        client
+            .close(Some(CloseFrame {
+                code: CloseCode::from(4000),
+                reason: "bye".into(),
+            }))
+            .await
+            .unwrap();
+        while client.next().await.is_some() {}
+        tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+
+        let events = events.lock().unwrap();
+        let summary: Vec<_> = events
+            .iter()
+            .map(|event| {
+                (
+                    event.side,
+                    event.code,
+                    event.reason.as_str(),
+                    event.error.is_some(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            summary,
+            [
+                (Side::Upstream, 4001, "done", false),
+                (Side::Upstream, 1011, "upstream connection failed", true),
+                (Side::Client, 4000, "bye", false),
+            ]
+        );
+        assert_eq!(events[0].upstream, format!("ws://{}/", upstream));
+        // the client answer to the upstream close, then the client close
+        assert_eq!(
+            *closes.lock().unwrap(),
+            [(4001, "done".to_string()), (4000, "bye".to_string())]
+        );
+    }
+
+    #[tokio::test]
+    async fn unreachable_upstreams_are_replied_502() {
+        let route = Route::new("", "http://127.0.0.1:1").websocket(WebSocket::new());
+        let (proxy, server) = warp::serve(route.filter()).bind_ephemeral(([127, 0, 0, 1], 0));
+        tokio::spawn(server);
+        match tokio_tungstenite::connect_async(format!("ws://{}", proxy)).await {
+            Err(Error::Http(response)) => assert_eq!(response.status(), 502),
+            other => panic!("unexpected {:?}", other.map(|_| ())),
+        }
+    }
+}